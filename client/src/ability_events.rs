@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::{
+    animations::AttackAnimation,
+    net::packet_handler::spawn_card_entity,
+    tilemap::{CardSprites, TileSize},
+    GameState, IsPlayer1,
+};
+use bevy::prelude::*;
+use common::{card::CardEntity, messages::AbilityEvent};
+
+pub struct AbilityEventsPlugin;
+
+impl Plugin for AbilityEventsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(AbilityEvents::default())
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing).with_system(play_ability_events),
+            );
+    }
+}
+
+// What triggered abilities did, waiting to be shown one at a time. Other
+// packets are held back until all of it has played.
+#[derive(Resource)]
+pub struct AbilityEvents {
+    pub queue: VecDeque<AbilityEvent>,
+    timer: Timer,
+}
+
+impl Default for AbilityEvents {
+    fn default() -> AbilityEvents {
+        AbilityEvents {
+            queue: VecDeque::new(),
+            timer: Timer::new(Duration::from_millis(300), TimerMode::Repeating),
+        }
+    }
+}
+
+fn play_ability_events(
+    mut events: ResMut<AbilityEvents>,
+    time: Res<Time>,
+    mut commands: Commands,
+    card_sprites: Res<CardSprites>,
+    tile_size: Res<TileSize>,
+    is_player_1: Res<IsPlayer1>,
+    mut card_entity_q: Query<(Entity, &mut CardEntity, &Transform)>,
+) {
+    if events.queue.is_empty() || !events.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let event = events.queue.pop_front().unwrap();
+    let at = match event {
+        AbilityEvent::Summoned(card_entity) => {
+            spawn_card_entity(
+                &mut commands,
+                &card_sprites,
+                tile_size.0,
+                is_player_1.0,
+                card_entity,
+            );
+            return;
+        }
+        AbilityEvent::Triggered { x, y, .. } | AbilityEvent::Died { x, y } => (x, y),
+        AbilityEvent::Damaged { ref troop, .. }
+        | AbilityEvent::Buffed { ref troop, .. }
+        | AbilityEvent::StatusAdded { ref troop, .. } => (troop.get_x_pos(), troop.get_y_pos()),
+    };
+    for (entity, mut card_entity, transform) in card_entity_q.iter_mut() {
+        if (card_entity.get_x_pos(), card_entity.get_y_pos()) != at {
+            continue;
+        }
+        match event {
+            AbilityEvent::Triggered { .. } => {
+                // a little hop to show which troop it was
+                let initial = Vec2::new(transform.translation.x, transform.translation.y);
+                commands.entity(entity).insert(AttackAnimation {
+                    target: initial + Vec2::new(0., tile_size.0 * 0.2),
+                    initial,
+                    moving_back: false,
+                });
+            }
+            AbilityEvent::Died { .. } => commands.entity(entity).despawn_recursive(),
+            AbilityEvent::Damaged { troop, .. }
+            | AbilityEvent::Buffed { troop, .. }
+            | AbilityEvent::StatusAdded { troop, .. } => *card_entity = troop,
+            AbilityEvent::Summoned(_) => {}
+        }
+        break;
+    }
+}
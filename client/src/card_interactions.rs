@@ -1,11 +1,14 @@
+use std::collections::HashSet;
 use std::rc::Rc;
 use std::sync::Arc;
 
 use crate::utils::screen_to_world_position;
-use crate::{net::QueueOut, tilemap::TileSize, GameState, IsPlayer1, IsSelfTurn, MainCamera};
+use crate::{
+    net::QueueOut, tilemap::TileSize, BoardMap, GameState, IsPlayer1, IsSelfTurn, MainCamera,
+};
 use bevy::prelude::*;
 
-use common::{card::CardEntity, messages::ClientMessage};
+use common::{board, card::CardEntity, messages::ClientMessage, status::Status};
 
 pub(crate) struct CardInteractions;
 
@@ -137,52 +140,65 @@ fn setting_indicators_system(
     selected_card_entity: Res<SelectedCardEntity>,
     is_player_1: Res<IsPlayer1>,
     is_self_turn: Res<IsSelfTurn>,
+    board_map: Res<BoardMap>,
 ) {
     if selected_card_entity.0.is_some() {
         let selected_card_entity = selected_card_entity.0.clone().unwrap();
-        if selected_card_entity.stun_count > 0 || selected_card_entity.get_y_pos() == 0 {
+        let selected_tile = (
+            selected_card_entity.get_x_pos(),
+            selected_card_entity.get_y_pos(),
+        );
+        if selected_card_entity.has_status(Status::Stun) || board_map.0.is_goal(selected_tile, true)
+        {
+            // the previously selected troop's indicators may still be up
+            for (_, mut visibility) in move_indicator_q.iter_mut() {
+                visibility.is_visible = false;
+            }
+            for (_, mut visibility) in attack_indicator_q.iter_mut() {
+                visibility.is_visible = false;
+            }
             return;
         }
 
+        let occupied: HashSet<(i32, i32)> = card_entity_q
+            .iter()
+            .map(|card_entity| (card_entity.get_x_pos(), card_entity.get_y_pos()))
+            .collect();
+        let taunting: Vec<(i32, i32)> = card_entity_q
+            .iter()
+            .filter(|card_entity| {
+                card_entity.is_owned_by_p1() != is_player_1.0
+                    && card_entity.has_status(Status::Taunt)
+            })
+            .map(|card_entity| (card_entity.get_x_pos(), card_entity.get_y_pos()))
+            .collect();
+        let reachable =
+            board::reachable_tiles(&board_map.0, &selected_card_entity, selected_tile, |tile| {
+                occupied.contains(&tile)
+            });
         for (move_indicator, mut visibility) in move_indicator_q.iter_mut() {
             if selected_card_entity.has_moved() || selected_card_entity.has_attacked() {
                 break;
             }
-            if Vec2::new(move_indicator.0 as f32, move_indicator.1 as f32).distance(Vec2::new(
-                selected_card_entity.get_x_pos() as f32,
-                selected_card_entity.get_y_pos() as f32,
-            )) < 1.5
-            {
-                let mut available = true;
-                for card_entity in card_entity_q.iter() {
-                    if card_entity.get_x_pos() == move_indicator.0
-                        && card_entity.get_y_pos() == move_indicator.1
-                    {
-                        available = false;
-                        break;
-                    }
-                }
-                if available && is_self_turn.0 {
-                    visibility.is_visible = true;
-                }
-            } else {
-                visibility.is_visible = false;
-            }
+            visibility.is_visible =
+                is_self_turn.0 && reachable.contains(&(move_indicator.0, move_indicator.1));
         }
 
         for (mut attack_indicator, mut visibility) in attack_indicator_q.iter_mut() {
             if selected_card_entity.has_attacked() {
                 break;
             }
-            if Vec2::new(attack_indicator.0 as f32, attack_indicator.1 as f32).distance(Vec2::new(
-                selected_card_entity.get_x_pos() as f32,
-                selected_card_entity.get_y_pos() as f32,
-            )) < 1.5
-            {
+            let target = (attack_indicator.0, attack_indicator.1);
+            if board::may_attack(
+                &selected_card_entity.get_card(),
+                selected_tile,
+                target,
+                &taunting,
+                |tile| occupied.contains(&tile),
+            ) {
                 let mut available = false;
                 for card_entity in card_entity_q.iter() {
-                    if card_entity.get_x_pos() == attack_indicator.0
-                        && card_entity.get_y_pos() == attack_indicator.1
+                    if (card_entity.get_x_pos(), card_entity.get_y_pos()) == target
                         && card_entity.is_owned_by_p1() != is_player_1.0
                     {
                         available = true;
@@ -239,7 +255,6 @@ fn card_interactions_system(
     for (move_indicator, visibility, transform) in move_indicator_q.iter() {
         if is_transform_clicked(transform, &mouse, &windows, &tile_size, &cam_q) {
             if visibility.is_visible {
-                let mut queue_out_guard = queue_out.0.lock().unwrap();
                 let acting_entity = selected_card_entity.0.clone().unwrap();
                 for mut card_entity in card_entity_q.iter_mut() {
                     if card_entity.get_x_pos() == acting_entity.get_x_pos()
@@ -248,7 +263,7 @@ fn card_interactions_system(
                         card_entity.moved();
                     }
                 }
-                queue_out_guard.push_back(ClientMessage::MoveTroop(
+                queue_out.send(ClientMessage::MoveTroop(
                     acting_entity.get_x_pos(),
                     acting_entity.get_y_pos(),
                     move_indicator.0,
@@ -263,7 +278,6 @@ fn card_interactions_system(
     for (attack_indicator, visibility, transform) in attack_indicator_q.iter() {
         if is_transform_clicked(transform, &mouse, &windows, &tile_size, &cam_q) {
             if visibility.is_visible {
-                let mut queue_out_guard = queue_out.0.lock().unwrap();
                 let acting_entity = selected_card_entity.0.clone().unwrap();
                 for mut card_entity in card_entity_q.iter_mut() {
                     if card_entity.get_x_pos() == acting_entity.get_x_pos()
@@ -272,7 +286,7 @@ fn card_interactions_system(
                         card_entity.moved();
                     }
                 }
-                queue_out_guard.push_back(ClientMessage::AttackTroop(
+                queue_out.send(ClientMessage::AttackTroop(
                     acting_entity.get_x_pos(),
                     acting_entity.get_y_pos(),
                     attack_indicator.0,
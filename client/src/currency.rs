@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use common::rules::RuleSet;
 
 use crate::GameState;
 
@@ -12,13 +13,16 @@ pub struct CurrencyPlugin;
 
 impl Plugin for CurrencyPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(Spirits(8))
-            .insert_resource(Pawns(6))
+        let rules = RuleSet::default();
+        app.insert_resource(Spirits(rules.starting_spirits))
+            .insert_resource(Pawns(rules.starting_pawns))
             .add_system_set(SystemSet::on_enter(GameState::Waiting).with_system(reset_currencies));
     }
 }
 
+// until the server says otherwise at `StartGame`
 fn reset_currencies(mut spirits: ResMut<Spirits>, mut pawns: ResMut<Pawns>) {
-    spirits.0 = 8;
-    pawns.0 = 6;
+    let rules = RuleSet::default();
+    spirits.0 = rules.starting_spirits;
+    pawns.0 = rules.starting_pawns;
 }
@@ -2,7 +2,7 @@ use bevy::{prelude::*, diagnostic::{FrameTimeDiagnosticsPlugin, Diagnostics}};
 use bevy_egui::{egui, EguiContext};
 
 
-use crate::DevMode;
+use crate::{net::Latency, DevMode};
 
 pub struct DebugPlugin;
 
@@ -16,7 +16,8 @@ impl Plugin for DebugPlugin{
 fn debug_ui(
     mut context: ResMut<EguiContext>,
     dev_mode: Res<DevMode>,
-    diagnostics: Res<Diagnostics>
+    diagnostics: Res<Diagnostics>,
+    latency: Option<Res<Latency>>,
 ){
     if dev_mode.0{
         egui::Window::new("Debug").show(context.ctx_mut(), |ui|{
@@ -25,6 +26,10 @@ fn debug_ui(
             if let Some(fps) = fps {
                 ui.monospace(format!("FPS: {}", fps.round()));
             }
+            match latency.as_ref().and_then(|latency| *latency.0.lock().unwrap()) {
+                Some(latency) => ui.monospace(format!("Ping: {} ms", latency.as_millis())),
+                None => ui.monospace("Ping: -"),
+            };
         });
     }
 }
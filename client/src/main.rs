@@ -7,6 +7,7 @@ use currency::CurrencyPlugin;
 
 
 
+pub mod ability_events;
 pub mod animations;
 pub mod audio;
 pub mod card_interactions;
@@ -15,20 +16,23 @@ pub mod debug;
 pub mod net;
 pub mod opening;
 pub mod ownership_indicator;
-pub mod stun_indicator;
+pub mod status_indicator;
 pub mod tilemap;
 pub mod ui;
 pub mod utils;
 
+use ability_events::AbilityEventsPlugin;
 use animations::AnimationPlugin;
 use audio::GameAudioPlugin;
 use card_interactions::CardInteractions;
 use common::card::{Card, CardNameToSprite};
+use common::map::Map;
+use common::rules::RuleSet;
 use debug::DebugPlugin;
-use net::packet_handler::PacketHandlerPlugin;
+use net::{packet_handler::PacketHandlerPlugin, NetPlugin};
 use opening::OpeningPlugin;
 use ownership_indicator::OwnershipIndicatorPlugin;
-use stun_indicator::StunIndicatorPlugin;
+use status_indicator::StatusIndicatorPlugin;
 use tilemap::TilemapPlugin;
 use ui::UiPlugin;
 
@@ -52,6 +56,14 @@ pub struct IsSelfTurn(pub bool);
 #[derive(Clone, Debug, Resource)]
 pub struct Deck(pub Vec<Card>);
 
+// the rules of the current game, as sent by the server
+#[derive(Clone, Debug, Resource)]
+pub struct GameRules(pub RuleSet);
+
+// the map as this player sees it, see `Map::seen_by`
+#[derive(Clone, Debug, Resource)]
+pub struct BoardMap(pub Map);
+
 #[derive(Resource)]
 pub struct DevMode(bool);
 
@@ -62,6 +74,8 @@ pub fn main() {
         .insert_resource(CardNameToSprite::default())
         .insert_resource(IsSelfTurn(false))
         .insert_resource(IsPlayer1(false))
+        .insert_resource(GameRules(RuleSet::default()))
+        .insert_resource(BoardMap(Map::default()))
         .add_plugins(
             DefaultPlugins
                 .set(WindowPlugin {
@@ -80,10 +94,12 @@ pub fn main() {
         .add_plugin(AudioPlugin)
         .add_plugin(TilemapPlugin)
         .add_plugin(UiPlugin)
+        .add_plugin(NetPlugin)
         .add_plugin(PacketHandlerPlugin)
+        .add_plugin(AbilityEventsPlugin)
         .add_plugin(CardInteractions)
         .add_plugin(CurrencyPlugin)
-        .add_plugin(StunIndicatorPlugin)
+        .add_plugin(StatusIndicatorPlugin)
         .add_plugin(OwnershipIndicatorPlugin)
         .add_plugin(GameAudioPlugin)
         .add_plugin(OpeningPlugin)
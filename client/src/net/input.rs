@@ -1,39 +1,98 @@
 use std::{
     collections::VecDeque,
-    io::{BufReader, Cursor, Read},
-    net::TcpStream,
+    io::{self, BufReader},
     sync::{Arc, Mutex},
-    thread,
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
-use byteorder::{BigEndian, ReadBytesExt};
-use common::messages::ServerMessage;
+use crossbeam_channel::Sender;
+
+use common::{
+    codec::Codec,
+    framing::{self, FrameError},
+    messages::{ClientMessage, ServerMessage},
+};
+
+use super::{out::ping_timestamp, stream::Connection, ConnectionEvent};
 
 pub(crate) fn spawn_input_thread(
     queue_in_ref: Arc<Mutex<VecDeque<ServerMessage>>>,
-    mut reader: BufReader<TcpStream>,
-) {
+    mut reader: BufReader<Connection>,
+    alive: Sender<()>,
+    events: Sender<ConnectionEvent>,
+    replies: Sender<ClientMessage>,
+    latency: Arc<Mutex<Option<Duration>>>,
+) -> JoinHandle<Option<String>> {
     thread::spawn(move || {
-        let mut received_data: usize;
-        let mut cursor: Cursor<[u8; 4]>;
+        // dropped when this thread ends, which stops the output thread
+        let _alive = alive;
+        // the server answers our handshake in JSON and switches afterwards
+        let mut codec = Codec::Json;
+        // the server tells us why before it closes the connection on purpose,
+        // which explains the EOF and means there's no point reconnecting
+        let mut closing_reason = None;
         loop {
-            let mut buffer_size: [u8; 4] = [0; 4];
-            reader.read(&mut buffer_size);
-            cursor = Cursor::new(buffer_size);
-            let mut buffer = vec![0; cursor.read_u32::<BigEndian>().unwrap() as usize];
-            received_data = reader.read(&mut buffer).unwrap_or(0);
-            if received_data > 0 {
-                if let Ok(string) = std::str::from_utf8_mut(&mut buffer) {
-                    if let Ok(message) = serde_json::from_str(string) {
-                        let mut guard = queue_in_ref.lock().unwrap();
-                        guard.push_back(message);
-                    } else {
-                        println!("Got an invalid packet!")
+            match framing::read_message::<_, ServerMessage>(&mut reader, codec) {
+                Ok(ServerMessage::Handshake(accepted)) => {
+                    codec = accepted;
+                }
+                Ok(ServerMessage::Ping(value)) => {
+                    let _ = replies.send(ClientMessage::Pong(value));
+                }
+                Ok(ServerMessage::Pong(sent)) => {
+                    let round_trip = ping_timestamp().saturating_sub(sent);
+                    *latency.lock().unwrap() = Some(Duration::from_millis(round_trip));
+                }
+                Ok(ServerMessage::Disconnect(reason)) => {
+                    bevy::log::warn!("The server is closing the connection: {}", reason);
+                    closing_reason = Some(reason);
+                }
+                Ok(message) => {
+                    match message {
+                        ServerMessage::ServerShuttingDown { .. } => {
+                            closing_reason = Some("the server is shutting down".to_owned());
+                        }
+                        // the connection only lasts one game
+                        ServerMessage::EndGame(_) => {
+                            closing_reason = Some("the game is over".to_owned());
+                        }
+                        _ => {}
                     }
+                    let mut guard = queue_in_ref.lock().unwrap();
+                    guard.push_back(message);
+                }
+                Err(FrameError::Malformed(e)) => {
+                    bevy::log::warn!("Got an invalid packet: {}", e);
+                }
+                Err(FrameError::Eof) => {
+                    bevy::log::info!("Server closed the connection");
+                    let reason = closing_reason
+                        .clone()
+                        .unwrap_or_else(|| "the server closed the connection".to_owned());
+                    let _ = events.send(ConnectionEvent::Lost(reason));
+                    break;
+                }
+                // the server pings us every few seconds, see `READ_TIMEOUT`
+                Err(FrameError::Io(e))
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    bevy::log::error!("The server stopped responding");
+                    let _ = events.send(ConnectionEvent::Lost(
+                        "the server stopped responding".to_owned(),
+                    ));
+                    break;
+                }
+                Err(e) => {
+                    bevy::log::error!("Lost connection to the server: {}", e);
+                    let _ = events.send(ConnectionEvent::Lost(e.to_string()));
+                    break;
                 }
-            } else {
-                break;
             }
         }
-    });
+        closing_reason
+    })
 }
@@ -1,37 +1,221 @@
 mod input;
 mod out;
 pub(crate) mod packet_handler;
+mod stream;
+pub(crate) mod tls;
 
 use std::{
     collections::VecDeque,
-    error::Error,
-    io::{BufReader},
-    net::TcpStream,
+    io::BufReader,
     sync::{Arc, Mutex},
+    thread,
+    time::Duration,
 };
 
 use bevy::prelude::*;
-use common::messages::{ClientMessage, ServerMessage};
+use common::{
+    codec::Codec,
+    messages::{ClientMessage, ServerMessage},
+};
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use rustls::ClientConfig;
+use stream::Connection;
+use tls::TlsMode;
+
+// how many times connecting is retried before giving up
+const CONNECT_RETRIES: u32 = 3;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// the server pings every few seconds, so this much silence means it's gone
+const READ_TIMEOUT: Duration = Duration::from_secs(20);
+
+pub(crate) struct NetPlugin;
+
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ConnectionEvent>()
+            .add_system(forward_connection_events);
+    }
+}
 
 #[derive(Resource)]
 pub(crate) struct QueueIn(pub(crate) Arc<Mutex<VecDeque<ServerMessage>>>);
+// Dropping this resource closes the connection: the output thread stops as
+// soon as no sender is left.
 #[derive(Resource)]
-pub(crate) struct QueueOut(pub(crate) Arc<Mutex<VecDeque<ClientMessage>>>);
+pub(crate) struct QueueOut(pub(crate) Sender<ClientMessage>);
 
-pub(crate) fn init(commands: &mut Commands, server_address: &str) -> Result<(), Box<dyn Error>> {
-    let stream = TcpStream::connect(server_address)?;
-    let queue_in: VecDeque<ServerMessage> = VecDeque::new();
-    let queue_out: VecDeque<ClientMessage> = VecDeque::new();
+impl QueueOut {
+    pub(crate) fn send(&self, message: ClientMessage) {
+        // if the output thread is gone it has already reported why
+        let _ = self.0.send(message);
+    }
+}
 
+// Round trip time of the last ping, shown in the debug window.
+#[derive(Resource)]
+pub(crate) struct Latency(pub(crate) Arc<Mutex<Option<Duration>>>);
+
+// Lifecycle of the connection to the server, sent by the network threads.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ConnectionEvent {
+    Connecting,
+    Connected,
+    // the previous attempt failed or the connection was lost, this is retry
+    // number `attempt`
+    Reconnecting { attempt: u32, max_attempts: u32 },
+    // connecting gave up
+    Failed(String),
+    // an established connection died, followed by `Reconnecting` unless the
+    // server closed it on purpose
+    Lost(String),
+}
+
+// Removing this resource mutes a connection we no longer care about.
+#[derive(Resource)]
+pub(crate) struct ConnectionEvents(Receiver<ConnectionEvent>);
+
+fn forward_connection_events(
+    events: Option<Res<ConnectionEvents>>,
+    mut writer: EventWriter<ConnectionEvent>,
+) {
+    if let Some(events) = events {
+        writer.send_batch(events.0.try_iter());
+    }
+}
+
+// Connects in the background, and again whenever the connection is lost
+// unexpectedly. Progress arrives as `ConnectionEvent`s; the queues can be used
+// straight away and packets go out once connected.
+pub(crate) fn connect(
+    commands: &mut Commands,
+    server_address: &str,
+    codec: Codec,
+    tls_mode: TlsMode,
+    tls_certificate: &str,
+) {
+    let queue_in: VecDeque<ServerMessage> = VecDeque::new();
     let queue_in_arc = Arc::new(Mutex::new(queue_in));
-    let queue_out_arc = Arc::new(Mutex::new(queue_out));
+    let (queue_out, outgoing) = crossbeam_channel::unbounded();
+    let (event_sender, events) = crossbeam_channel::unbounded();
+    let latency = Arc::new(Mutex::new(None));
+
+    let queue_in_ref = Arc::clone(&queue_in_arc);
+    let latency_ref = Arc::clone(&latency);
+    let server_address = server_address.to_owned();
+    let tls_certificate = tls_certificate.to_owned();
+    thread::spawn(move || {
+        let _ = event_sender.send(ConnectionEvent::Connecting);
+        // retrying won't fix a broken certificate file
+        let tls_config = match tls::client_config(tls_mode, &tls_certificate) {
+            Ok(tls_config) => tls_config,
+            Err(e) => {
+                bevy::log::error!("Could not set up TLS: {}", e);
+                let _ = event_sender.send(ConnectionEvent::Failed(e.to_string()));
+                return;
+            }
+        };
+        let mut reconnecting = false;
+        loop {
+            let (stream, cloned_stream) =
+                match open(&server_address, &tls_config, &event_sender, reconnecting) {
+                    Some(streams) => streams,
+                    None => return,
+                };
+            bevy::log::info!(
+                "Successfully connected{}",
+                if tls_config.is_some() {
+                    " over TLS"
+                } else {
+                    ""
+                }
+            );
+            let _ = event_sender.send(ConnectionEvent::Connected);
 
-    let cloned_stream = stream.try_clone()?;
-    input::spawn_input_thread(Arc::clone(&queue_in_arc), BufReader::new(cloned_stream));
-    out::spawn_output_thread(Arc::clone(&queue_out_arc), stream);
+            // never written to, only dropped when the input thread stops
+            let (input_alive, input_closed) = crossbeam_channel::bounded::<()>(0);
+            let (reply_sender, replies) = crossbeam_channel::unbounded();
+            let input = input::spawn_input_thread(
+                Arc::clone(&queue_in_ref),
+                BufReader::new(cloned_stream),
+                input_alive,
+                event_sender.clone(),
+                reply_sender,
+                Arc::clone(&latency_ref),
+            );
+            let output = out::spawn_output_thread(
+                outgoing.clone(),
+                replies,
+                input_closed,
+                event_sender.clone(),
+                stream,
+                codec,
+            );
+            // the output thread follows the input thread, and has to be gone
+            // before the next one takes over `outgoing`
+            let closing_reason = input.join().unwrap_or_default();
+            let _ = output.join();
+            if closing_reason.is_some() {
+                return;
+            }
+            // whatever is still queued was meant for the lost connection, and a
+            // dropped `QueueOut` means nobody is waiting for a new one
+            loop {
+                match outgoing.try_recv() {
+                    Ok(_) => {}
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            *latency_ref.lock().unwrap() = None;
+            reconnecting = true;
+        }
+    });
 
     commands.insert_resource(QueueIn(queue_in_arc));
-    commands.insert_resource(QueueOut(queue_out_arc));
-    bevy::log::info!("Successfully established TCP connection");
-    Ok(())
+    commands.insert_resource(QueueOut(queue_out));
+    commands.insert_resource(ConnectionEvents(events));
+    commands.insert_resource(Latency(latency));
+}
+
+// Opens the connection, retrying with a growing delay. After a lost connection
+// every attempt counts as a retry.
+fn open(
+    server_address: &str,
+    tls_config: &Option<Arc<ClientConfig>>,
+    events: &Sender<ConnectionEvent>,
+    reconnecting: bool,
+) -> Option<(Connection, Connection)> {
+    let mut attempt = 0;
+    if reconnecting {
+        attempt += 1;
+        let _ = events.send(ConnectionEvent::Reconnecting {
+            attempt,
+            max_attempts: CONNECT_RETRIES,
+        });
+    }
+    loop {
+        let opened = Connection::open(server_address, tls_config.clone(), CONNECT_TIMEOUT)
+            .and_then(|stream| {
+                let cloned_stream = stream.try_clone()?;
+                cloned_stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                Ok((stream, cloned_stream))
+            });
+        match opened {
+            Ok(streams) => return Some(streams),
+            Err(e) if attempt < CONNECT_RETRIES => {
+                bevy::log::warn!("Could not connect to the server: {}", e);
+                attempt += 1;
+                let _ = events.send(ConnectionEvent::Reconnecting {
+                    attempt,
+                    max_attempts: CONNECT_RETRIES,
+                });
+                thread::sleep(Duration::from_secs(attempt as u64));
+            }
+            Err(e) => {
+                bevy::log::error!("Could not connect to the server: {}", e);
+                let _ = events.send(ConnectionEvent::Failed(e.to_string()));
+                return None;
+            }
+        }
+    }
 }
@@ -1,29 +1,61 @@
-use std::{
-    collections::VecDeque,
-    io::Write,
-    net::TcpStream,
-    sync::{Arc, Mutex},
-    thread,
-};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use byteorder::{BigEndian, WriteBytesExt};
-use common::messages::ClientMessage;
-use serde_json;
+use common::{codec::Codec, framing, messages::ClientMessage};
+use crossbeam_channel::{select, tick, Receiver, Sender};
 
+use super::{stream::Connection, ConnectionEvent};
+
+// how often we ping the server to measure latency
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+
+// milliseconds since the epoch, echoed back by the server in its `Pong`
+pub(crate) fn ping_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64)
+}
+
+// Blocks until there is something to send. Stops when the app drops
+// `QueueOut`, when the input thread sees the connection close, or on the
+// first write error, which is reported as a lost connection. `replies` carries
+// the input thread's answers to server pings.
 pub(crate) fn spawn_output_thread(
-    queue_out_ref: Arc<Mutex<VecDeque<ClientMessage>>>,
-    mut stream: TcpStream,
-) {
-    thread::spawn(move || loop {
-        let mut guard = queue_out_ref.lock().unwrap();
-        if let Some(binding) = guard.pop_front() {
-            if let Ok(string) = serde_json::to_string(&binding) {
-                let bytes = string.as_bytes();
-                let mut wtr = vec![];
-                wtr.write_u32::<BigEndian>(bytes.len() as u32).unwrap();
-                stream.write(wtr.as_slice());
-                stream.write(bytes);
+    outgoing: Receiver<ClientMessage>,
+    replies: Receiver<ClientMessage>,
+    input_closed: Receiver<()>,
+    events: Sender<ConnectionEvent>,
+    mut stream: Connection,
+    codec: Codec,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) =
+            framing::write_message(&mut stream, Codec::Json, &ClientMessage::Handshake(codec))
+        {
+            bevy::log::error!("Couldn't send handshake: {}", e);
+            let _ = events.send(ConnectionEvent::Lost(e.to_string()));
+            return;
+        }
+        let ping = tick(PING_INTERVAL);
+        loop {
+            let message = select! {
+                recv(outgoing) -> message => match message {
+                    Ok(message) => message,
+                    Err(_) => break,
+                },
+                recv(replies) -> message => match message {
+                    Ok(message) => message,
+                    Err(_) => break,
+                },
+                recv(ping) -> _ => ClientMessage::Ping(ping_timestamp()),
+                recv(input_closed) -> _ => break,
+            };
+            if let Err(e) = framing::write_message(&mut stream, codec, &message) {
+                bevy::log::error!("Couldn't send packet: {}", e);
+                let _ = events.send(ConnectionEvent::Lost(e.to_string()));
+                break;
             }
         }
-    });
+        stream.shutdown();
+    })
 }
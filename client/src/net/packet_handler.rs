@@ -1,21 +1,19 @@
-use super::QueueIn;
+use super::{ConnectionEvents, QueueIn};
 use crate::{
+    ability_events::AbilityEvents,
     animations::AttackAnimation,
-    card_interactions::{SelectIndicator, ViewingCardEntity},
+    card_interactions::{AttackIndicator, MoveIndicator, SelectIndicator, ViewingCardEntity},
     currency::{Pawns, Spirits},
     ownership_indicator::OwnershipIndicator,
-    tilemap::{self, CardSprites, TileSize},
-    ui::{
-        in_game_ui::{EndTurnButtonLabel, TurnIndicator},
-        GameFont,
-    },
-    GameState, IsPlayer1, IsSelfTurn,
+    tilemap::{self, CardSprites, Tile, TileSize},
+    ui::in_game_ui::{EndTurnButtonLabel, TurnIndicator},
+    BoardMap, GameRules, GameState, IsPlayer1, IsSelfTurn, MainCamera,
 };
 use belly::prelude::*;
 use bevy::prelude::*;
 use common::{
-    card::{CardAbility, CardEntity},
-    messages::ServerMessage,
+    card::CardEntity,
+    messages::{AttackResult, ServerMessage},
 };
 
 pub(crate) struct PacketHandlerPlugin;
@@ -25,6 +23,8 @@ impl Plugin for PacketHandlerPlugin {
         app.add_system_set(
             SystemSet::on_update(GameState::PreparingForGame).with_system(handle_packets),
         )
+        .add_event::<LeaveGame>()
+        .add_system(leave_game)
         .insert_resource(ChatMessages(Vec::new()))
         .add_system_set(SystemSet::on_update(GameState::Playing).with_system(handle_packets));
     }
@@ -33,13 +33,20 @@ impl Plugin for PacketHandlerPlugin {
 #[derive(Resource, Clone)]
 pub struct ChatMessages(pub Vec<String>);
 
-fn handle_packets(
-    queue_in: ResMut<QueueIn>,
+// Tears down the board and goes back to the main menu, whether the game ended
+// normally or the connection was lost. After a reconnect the player waits for
+// the next opponent instead.
+pub(crate) struct LeaveGame {
+    pub(crate) rejoin_queue: bool,
+}
+
+fn leave_game(
+    mut reader: EventReader<LeaveGame>,
     mut commands: Commands,
     mut state: ResMut<State<GameState>>,
-    card_sprites: Res<CardSprites>,
-    tile_size: Res<TileSize>,
-    mut card_entity_q: Query<(Entity, &mut CardEntity, &Transform)>,
+    card_entity_q: Query<Entity, With<CardEntity>>,
+    board_q: Query<Entity, Or<(With<Tile>, With<MoveIndicator>, With<AttackIndicator>)>>,
+    mut projection_q: Query<&mut OrthographicProjection, With<MainCamera>>,
     mut visible_q: Query<
         &mut Visibility,
         (
@@ -48,20 +55,79 @@ fn handle_packets(
             Without<SelectIndicator>,
         ),
     >,
+    mut elements: Elements,
+    mut ability_events: ResMut<AbilityEvents>,
+) {
+    let rejoin_queue = match reader.iter().last() {
+        Some(event) => event.rejoin_queue,
+        None => return,
+    };
+    ability_events.queue.clear();
+    for entity in card_entity_q.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // the next game's map may be different
+    for entity in board_q.iter() {
+        commands.entity(entity).despawn();
+    }
+    projection_q.single_mut().scale = 1.;
+    for mut visibility in visible_q.iter_mut() {
+        visibility.is_visible = false;
+    }
+    commands.add(|world: &mut World| {
+        world
+            .query_filtered::<&mut Visibility, With<SelectIndicator>>()
+            .single_mut(world)
+            .is_visible = false;
+        world.get_resource_mut::<ViewingCardEntity>().unwrap().0 = None;
+    });
+    elements.select("body").remove();
+    if rejoin_queue {
+        state.set(GameState::PreparingForGame).unwrap();
+        return;
+    }
+    // the server closes the socket after the game, which isn't worth reporting
+    commands.remove_resource::<ConnectionEvents>();
+    if *state.current() != GameState::Waiting {
+        state.set(GameState::Waiting).unwrap();
+    }
+}
+
+fn handle_packets(
+    queue_in: Option<Res<QueueIn>>,
+    mut commands: Commands,
+    mut state: ResMut<State<GameState>>,
+    card_sprites: Res<CardSprites>,
+    tile_size: Res<TileSize>,
+    mut card_entity_q: Query<(Entity, &mut CardEntity, &Transform)>,
     mut is_self_turn: ResMut<IsSelfTurn>,
     mut is_player_1_res: ResMut<IsPlayer1>,
     mut pawn_count: ResMut<Pawns>,
     mut spirit_count: ResMut<Spirits>,
     mut turn_label_q: Query<&mut Label, (With<TurnIndicator>, Without<CardEntity>)>,
-    game_font: Res<GameFont>,
     asset_server: Res<AssetServer>,
     mut elements: Elements,
     mut messages: ResMut<ChatMessages>,
+    mut leave_game: EventWriter<LeaveGame>,
+    mut ability_events: ResMut<AbilityEvents>,
 ) {
+    let queue_in = match queue_in {
+        Some(queue_in) => queue_in,
+        None => return,
+    };
+    // the rest has to wait until abilities are done playing out
+    if !ability_events.queue.is_empty() {
+        return;
+    }
     let mut guard = queue_in.0.lock().unwrap();
     if let Some(message) = guard.pop_front() {
         match message {
-            ServerMessage::StartGame(is_player_1) => {
+            // consumed by the input thread
+            ServerMessage::Handshake(_)
+            | ServerMessage::Ping(_)
+            | ServerMessage::Pong(_)
+            | ServerMessage::Disconnect(_) => {}
+            ServerMessage::StartGame(is_player_1, rules) => {
                 if is_player_1 {
                     is_self_turn.0 = true;
                     is_player_1_res.0 = true;
@@ -69,47 +135,20 @@ fn handle_packets(
                     is_self_turn.0 = false;
                     is_player_1_res.0 = false;
                 }
+                pawn_count.0 = rules.starting_pawns;
+                spirit_count.0 = rules.starting_spirits;
+                commands.insert_resource(BoardMap(rules.map.seen_by(is_player_1)));
+                commands.insert_resource(GameRules(rules));
                 state.set(GameState::Playing).unwrap();
             }
             ServerMessage::SpawnCard(card_entity) => {
-                let mut sprite = TextureAtlasSprite::new(
-                    card_sprites
-                        .1
-                        .get(&card_entity.get_card().get_name())
-                        .unwrap()
-                        .clone(),
+                spawn_card_entity(
+                    &mut commands,
+                    &card_sprites,
+                    tile_size.0,
+                    is_player_1_res.0,
+                    card_entity,
                 );
-                sprite.custom_size = Some(Vec2::splat(tile_size.0 * 0.8));
-                let is_owned_by_p1 = card_entity.is_owned_by_p1();
-
-                commands
-                    .spawn(SpriteSheetBundle {
-                        sprite,
-                        texture_atlas: card_sprites.0.clone(),
-                        transform: Transform::from_xyz(1000000000.0, 1000000000.0, 500.),
-                        ..Default::default()
-                    })
-                    .insert(card_entity)
-                    .insert(tilemap::InstantMove)
-                    .with_children(move |parent| {
-                        let mut transform = Transform::default();
-                        transform.translation.z = 400.0;
-                        parent
-                            .spawn(SpriteBundle {
-                                sprite: Sprite {
-                                    custom_size: Some(Vec2::splat(tile_size.0 * 0.9)),
-                                    color: Color::hex(if is_owned_by_p1 == is_player_1_res.0 {
-                                        "2b8fc4"
-                                    } else {
-                                        "e0828a"
-                                    })
-                                    .unwrap(),
-                                    ..Default::default()
-                                },
-                                ..Default::default()
-                            })
-                            .insert(OwnershipIndicator);
-                    });
             }
             ServerMessage::MoveTroop(start_x, start_y, end_x, end_y) => {
                 for (_, mut card_entity, _) in card_entity_q.iter_mut() {
@@ -120,59 +159,75 @@ fn handle_packets(
                     }
                 }
             }
-            ServerMessage::AttackTroop(start_x, start_y, end_x, end_y) => {
+            ServerMessage::AttackResult(AttackResult {
+                from,
+                to,
+                attacker: attacker_after,
+                target: target_after,
+                ..
+            }) => {
                 let mut attacker: Option<(Entity, Mut<CardEntity>, &Transform)> = None;
                 let mut attacked: Option<(Entity, Mut<CardEntity>, &Transform)> = None;
                 for (entity, card_entity, transform) in card_entity_q.iter_mut() {
-                    if card_entity.clone().get_x_pos() == start_x
-                        && card_entity.clone().get_y_pos() == start_y
-                    {
+                    let at = (card_entity.get_x_pos(), card_entity.get_y_pos());
+                    if at == from {
                         attacker = Some((entity, card_entity, transform));
-                    } else if card_entity.clone().get_x_pos() == end_x
-                        && card_entity.clone().get_y_pos() == end_y
-                    {
+                    } else if at == to {
                         attacked = Some((entity, card_entity, transform));
                     }
                 }
-                let mut attacker = attacker.unwrap();
-                let mut attacked = attacked.unwrap();
-                attacked.1.current_hp -= attacker.1.get_card().get_damage();
-                attacker.1.attacked();
-                let abilities = attacker.1.get_card().get_abilities();
-                for ability in &abilities {
-                    if let CardAbility::Stun { amount } = ability {
-                        attacked.1.stun_count += amount;
+                let (mut attacker, mut attacked) = match (attacker, attacked) {
+                    (Some(attacker), Some(attacked)) => (attacker, attacked),
+                    _ => {
+                        bevy::log::warn!(
+                            "Got an attack from {:?} to {:?}, but there are no troops there",
+                            from,
+                            to
+                        );
+                        return;
                     }
-                }
+                };
                 commands.entity(attacker.0).insert(AttackAnimation {
                     target: Vec2::new(attacked.2.translation.x, attacked.2.translation.y),
                     initial: Vec2::new(attacker.2.translation.x, attacker.2.translation.y),
                     moving_back: false,
                 });
-                if attacked.1.current_hp <= 0. {
-                    commands.entity(attacked.0).despawn_recursive();
-                    attacker.1.set_x_pos(end_x);
-                    attacker.1.set_y_pos(end_y);
-                    if attacked.1.is_owned_by_p1() == is_player_1_res.0 {
-                        pawn_count.0 += 1;
-                    }
-                    if attacker.1.is_owned_by_p1() == is_player_1_res.0 {
-                        if abilities.contains(&CardAbility::SpiritCollector) {
-                            spirit_count.0 += attacked.1.get_card().get_cost();
-                        } else {
-                            spirit_count.0 += attacked.1.get_card().get_cost() / 2;
-                        }
+                match attacker_after {
+                    Some(card_entity) => *attacker.1 = card_entity,
+                    None => commands.entity(attacker.0).despawn_recursive(),
+                }
+                match target_after {
+                    Some(card_entity) => *attacked.1 = card_entity,
+                    None => commands.entity(attacked.0).despawn_recursive(),
+                }
+            }
+            ServerMessage::HpChanged { x, y, hp } => {
+                for (_, mut card_entity, _) in card_entity_q.iter_mut() {
+                    if card_entity.get_x_pos() == x && card_entity.get_y_pos() == y {
+                        card_entity.current_hp = hp;
                     }
                 }
             }
-            ServerMessage::StartTurn => {
-                is_self_turn.0 = true;
+            ServerMessage::AbilityEvents(events) => {
+                ability_events.queue.extend(events);
+            }
+            ServerMessage::TroopsChanged(troops) => {
                 for (_, mut card_entity, _) in card_entity_q.iter_mut() {
-                    if card_entity.is_owned_by_p1() == is_player_1_res.0 {
-                        card_entity.reset();
+                    let at = (card_entity.get_x_pos(), card_entity.get_y_pos());
+                    if let Some(troop) = troops
+                        .iter()
+                        .find(|troop| (troop.get_x_pos(), troop.get_y_pos()) == at)
+                    {
+                        *card_entity = troop.clone();
                     }
                 }
-                spirit_count.0 += 1;
+            }
+            ServerMessage::Currency { pawns, spirits } => {
+                pawn_count.0 = pawns;
+                spirit_count.0 = spirits;
+            }
+            ServerMessage::StartTurn => {
+                is_self_turn.0 = true;
                 if is_self_turn.0 {
                     let button_handle: Handle<Image> = asset_server.load("button.png");
                     let tile_size = tile_size.0;
@@ -197,43 +252,126 @@ fn handle_packets(
                 }
                 turn_label_q.single_mut().value = "Your Turn".to_string();
             }
+            ServerMessage::TurnTimedOut => {
+                // same as pressing "End Turn", minus telling the server
+                is_self_turn.0 = false;
+                elements.select("#end-turn-button").remove();
+                turn_label_q.single_mut().value = "Opponent's Turn".to_string();
+            }
             ServerMessage::EndGame(_won) => {
-                for (entity, _, _) in card_entity_q.iter() {
-                    commands.entity(entity).despawn_recursive();
-                }
-                for mut visibility in visible_q.iter_mut() {
-                    visibility.is_visible = false;
-                }
-                commands.add(|world: &mut World| {
-                    world
-                        .query_filtered::<&mut Visibility, With<SelectIndicator>>()
-                        .single_mut(world)
-                        .is_visible = false;
-                    world.get_resource_mut::<ViewingCardEntity>().unwrap().0 = None;
+                leave_game.send(LeaveGame {
+                    rejoin_queue: false,
                 });
-                elements.select("body").remove();
-                state.set(GameState::Waiting).unwrap();
             }
             ServerMessage::ChatMessage(message) => {
-                messages.0.push(message.clone());
-                let tile_size = tile_size.0;
-                let mut entities = elements.select(".chat-message").entities();
-                while entities.len() > 8 {
-                    commands.entity(entities.remove(0)).despawn_recursive();
-                }
-                elements.select("#chat-area").add_child(eml! {
-                    <label
-                        s:color="black"
-                        value=message.clone().as_str()
-                        s:font-size=format!("{}", tile_size / 4.0)
-                        s:top="0px"
-                        s:width="90%"
-                        s:left="0%"
-                        c:chat-message
-                        >
-                    </label>
-                });
+                show_chat_message(
+                    &mut commands,
+                    &mut elements,
+                    &mut messages,
+                    tile_size.0,
+                    message,
+                );
+            }
+            ServerMessage::ChatRejected(reason) => {
+                show_chat_message(
+                    &mut commands,
+                    &mut elements,
+                    &mut messages,
+                    tile_size.0,
+                    format!("[Not sent] {}", reason),
+                );
+            }
+            ServerMessage::Announcement(text) => {
+                show_chat_message(
+                    &mut commands,
+                    &mut elements,
+                    &mut messages,
+                    tile_size.0,
+                    format!("[Server] {}", text),
+                );
+            }
+            ServerMessage::ServerShuttingDown { seconds } => {
+                show_chat_message(
+                    &mut commands,
+                    &mut elements,
+                    &mut messages,
+                    tile_size.0,
+                    format!("[Server] Shutting down in {}s", seconds),
+                );
             }
         }
     }
 }
+
+pub(crate) fn spawn_card_entity(
+    commands: &mut Commands,
+    card_sprites: &CardSprites,
+    tile_size: f32,
+    is_player_1: bool,
+    card_entity: CardEntity,
+) {
+    let mut sprite = TextureAtlasSprite::new(
+        card_sprites
+            .1
+            .get(&card_entity.get_card().get_name())
+            .unwrap()
+            .clone(),
+    );
+    sprite.custom_size = Some(Vec2::splat(tile_size * 0.8));
+    let is_owned_by_p1 = card_entity.is_owned_by_p1();
+
+    commands
+        .spawn(SpriteSheetBundle {
+            sprite,
+            texture_atlas: card_sprites.0.clone(),
+            transform: Transform::from_xyz(1000000000.0, 1000000000.0, 500.),
+            ..Default::default()
+        })
+        .insert(card_entity)
+        .insert(tilemap::InstantMove)
+        .with_children(move |parent| {
+            let mut transform = Transform::default();
+            transform.translation.z = 400.0;
+            parent
+                .spawn(SpriteBundle {
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::splat(tile_size * 0.9)),
+                        color: Color::hex(if is_owned_by_p1 == is_player_1 {
+                            "2b8fc4"
+                        } else {
+                            "e0828a"
+                        })
+                        .unwrap(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(OwnershipIndicator);
+        });
+}
+
+fn show_chat_message(
+    commands: &mut Commands,
+    elements: &mut Elements,
+    messages: &mut ChatMessages,
+    tile_size: f32,
+    message: String,
+) {
+    messages.0.push(message.clone());
+    let mut entities = elements.select(".chat-message").entities();
+    while entities.len() > 8 {
+        commands.entity(entities.remove(0)).despawn_recursive();
+    }
+    elements.select("#chat-area").add_child(eml! {
+        <label
+            s:color="black"
+            value=message.clone().as_str()
+            s:font-size=format!("{}", tile_size / 4.0)
+            s:top="0px"
+            s:width="90%"
+            s:left="0%"
+            c:chat-message
+            >
+        </label>
+    });
+}
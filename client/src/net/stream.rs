@@ -0,0 +1,140 @@
+use std::{
+    error::Error,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rustls::{ClientConfig, ClientConnection, ServerName};
+
+// A connection to the server, plain or TLS. The input and output threads each
+// get their own clone; with TLS they share the session behind a mutex, which
+// is only held while records are processed, never while waiting on the socket.
+pub(crate) struct Connection {
+    socket: TcpStream,
+    tls: Option<Arc<Mutex<ClientConnection>>>,
+}
+
+fn open_socket(server_address: &str, timeout: Duration) -> Result<TcpStream, Box<dyn Error>> {
+    let mut last_error: Box<dyn Error> = "the address didn't resolve to anything".into();
+    for address in server_address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e.into(),
+        }
+    }
+    Err(last_error)
+}
+
+// the part of "host:port" a certificate has to be valid for
+fn server_name(server_address: &str) -> Result<ServerName, Box<dyn Error>> {
+    let host = match server_address.rsplit_once(':') {
+        Some((host, _port)) => host,
+        None => server_address,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok(ServerName::try_from(host)?)
+}
+
+impl Connection {
+    // Connects and, with a TLS config, completes the handshake before
+    // returning, so certificate problems show up as connection failures.
+    pub(crate) fn open(
+        server_address: &str,
+        tls: Option<Arc<ClientConfig>>,
+        timeout: Duration,
+    ) -> Result<Connection, Box<dyn Error>> {
+        let mut socket = open_socket(server_address, timeout)?;
+        let tls = match tls {
+            Some(config) => {
+                let mut session = ClientConnection::new(config, server_name(server_address)?)?;
+                socket.set_read_timeout(Some(timeout))?;
+                while session.is_handshaking() {
+                    session.complete_io(&mut socket)?;
+                }
+                socket.set_read_timeout(None)?;
+                Some(Arc::new(Mutex::new(session)))
+            }
+            None => None,
+        };
+        Ok(Connection { socket, tls })
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Connection> {
+        Ok(Connection {
+            socket: self.socket.try_clone()?,
+            tls: self.tls.clone(),
+        })
+    }
+
+    // reads that stay silent this long fail with `WouldBlock` or `TimedOut`
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+
+    pub(crate) fn shutdown(&mut self) {
+        if let Some(tls) = &self.tls {
+            let mut session = tls.lock().unwrap();
+            session.send_close_notify();
+            let _ = session.write_tls(&mut self.socket);
+        }
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let tls = match &self.tls {
+            Some(tls) => tls,
+            None => return self.socket.read(buf),
+        };
+        let mut records = [0; 4096];
+        loop {
+            {
+                let mut session = tls.lock().unwrap();
+                match session.reader().read(buf) {
+                    // rustls wants more records first
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    result => return result,
+                }
+            }
+            let len = self.socket.read(&mut records)?;
+            let mut received = &records[..len];
+            let mut session = tls.lock().unwrap();
+            loop {
+                // an empty read tells rustls the socket is closed
+                session.read_tls(&mut received)?;
+                session
+                    .process_new_packets()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                if received.is_empty() {
+                    break;
+                }
+            }
+            // alerts and key updates need an answer
+            while session.wants_write() {
+                session.write_tls(&mut self.socket)?;
+            }
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let tls = match &self.tls {
+            Some(tls) => tls,
+            None => return self.socket.write(buf),
+        };
+        let mut session = tls.lock().unwrap();
+        let len = session.writer().write(buf)?;
+        while session.wants_write() {
+            session.write_tls(&mut self.socket)?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
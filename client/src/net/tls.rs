@@ -0,0 +1,86 @@
+use std::{error::Error, fs::File, io::BufReader, sync::Arc, time::SystemTime};
+
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TlsMode {
+    #[default]
+    Off,
+    // the public certificate authorities, plus the certificate from settings
+    Verified,
+    // encrypted, but any certificate is accepted. Only for testing!
+    Insecure,
+}
+
+impl TlsMode {
+    pub fn next(self) -> TlsMode {
+        match self {
+            Self::Off => Self::Verified,
+            Self::Verified => Self::Insecure,
+            Self::Insecure => Self::Off,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Verified => "Verified",
+            Self::Insecure => "Insecure",
+        }
+    }
+}
+
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+// `certificate` is the path of a PEM file to trust on top of the public
+// authorities, e.g. a self-hosted server's self-signed certificate. Empty for
+// none.
+pub(crate) fn client_config(
+    mode: TlsMode,
+    certificate: &str,
+) -> Result<Option<Arc<ClientConfig>>, Box<dyn Error>> {
+    let builder = ClientConfig::builder().with_safe_defaults();
+    let config = match mode {
+        TlsMode::Off => return Ok(None),
+        TlsMode::Verified => {
+            let mut roots = RootCertStore::empty();
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+            if !certificate.is_empty() {
+                let file = File::open(certificate)
+                    .map_err(|e| format!("couldn't open {}: {}", certificate, e))?;
+                for der in rustls_pemfile::certs(&mut BufReader::new(file))? {
+                    roots.add(&Certificate(der))?;
+                }
+            }
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+        TlsMode::Insecure => builder
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+            .with_no_client_auth(),
+    };
+    Ok(Some(Arc::new(config)))
+}
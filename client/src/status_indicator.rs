@@ -0,0 +1,105 @@
+use crate::tilemap::TileSize;
+use crate::GameState;
+use bevy::{prelude::*, render::texture::DEFAULT_IMAGE_HANDLE};
+use common::{card::CardEntity, status::Status};
+
+pub struct StatusIndicatorPlugin;
+
+impl Plugin for StatusIndicatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system_to_stage(StartupStage::PostStartup, spawn_status_indicators)
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing).with_system(show_status_indicators),
+            );
+    }
+}
+
+#[derive(Component)]
+pub struct StatusIndicator;
+
+#[derive(Resource)]
+struct StatusSprites {
+    stun: Handle<Image>,
+}
+
+// Statuses with a sprite of their own cover the whole tile, the others get
+// a coloured mark along the top of it.
+enum Look {
+    Sprite(Handle<Image>),
+    Mark(Color),
+}
+
+fn look(status: Status, sprites: &StatusSprites) -> Look {
+    match status {
+        Status::Stun => Look::Sprite(sprites.stun.clone()),
+        Status::Poison => Look::Mark(Color::rgb_u8(95, 179, 74)),
+        Status::Burn => Look::Mark(Color::rgb_u8(232, 112, 42)),
+        Status::Slow => Look::Mark(Color::rgb_u8(111, 168, 220)),
+        Status::Root => Look::Mark(Color::rgb_u8(139, 90, 43)),
+        Status::Taunt => Look::Mark(Color::rgb_u8(212, 175, 55)),
+        Status::Silence => Look::Mark(Color::rgb_u8(158, 158, 158)),
+    }
+}
+
+fn spawn_status_indicators(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(StatusSprites {
+        stun: asset_server.load("stun_indicator.png"),
+    });
+    for _ in 0..64 {
+        commands
+            .spawn(SpriteBundle {
+                transform: Transform::from_xyz(0.0, 0.0, 425.0),
+                visibility: Visibility { is_visible: false },
+                ..Default::default()
+            })
+            .insert(StatusIndicator);
+    }
+}
+
+fn show_status_indicators(
+    mut status_indicator_q: Query<
+        (
+            &mut Transform,
+            &mut Visibility,
+            &mut Sprite,
+            &mut Handle<Image>,
+        ),
+        With<StatusIndicator>,
+    >,
+    card_entity_q: Query<(&CardEntity, &Transform), Without<StatusIndicator>>,
+    sprites: Res<StatusSprites>,
+    tile_size: Res<TileSize>,
+) {
+    let mut status_indicators = status_indicator_q.iter_mut();
+    for (card_entity, card_transform) in card_entity_q.iter() {
+        let mut marks = 0;
+        for effect in card_entity.get_statuses() {
+            let (mut transform, mut visibility, mut sprite, mut texture) =
+                match status_indicators.next() {
+                    Some(status_indicator) => status_indicator,
+                    None => return,
+                };
+            transform.translation = card_transform.translation;
+            transform.translation.z = 425.0;
+            visibility.is_visible = true;
+            match look(effect.status, &sprites) {
+                Look::Sprite(handle) => {
+                    *texture = handle;
+                    sprite.color = Color::WHITE;
+                    sprite.custom_size = Some(Vec2::splat(tile_size.0));
+                }
+                Look::Mark(color) => {
+                    *texture = DEFAULT_IMAGE_HANDLE.typed();
+                    sprite.color = color;
+                    sprite.custom_size = Some(Vec2::splat(tile_size.0 * 0.15));
+                    transform.translation.x += tile_size.0 * (0.2 * marks as f32 - 0.35);
+                    transform.translation.y += tile_size.0 * 0.4;
+                    marks += 1;
+                }
+            }
+        }
+    }
+    for (_, mut visibility, _, _) in status_indicators {
+        visibility.is_visible = false;
+    }
+}
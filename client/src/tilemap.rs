@@ -5,9 +5,10 @@ use crate::{
     card_interactions::{AttackIndicator, MoveIndicator},
     ui::settings::update_window_scale,
     ui::settings::Settings,
-    GameState, IsPlayer1,
+    BoardMap, GameState, IsPlayer1, MainCamera,
 };
 use common::card::CardEntity;
+use common::map::{Map, Terrain};
 use std::collections::HashMap;
 
 #[derive(Resource)]
@@ -21,15 +22,13 @@ pub struct TilemapPlugin;
 impl Plugin for TilemapPlugin {
     fn build(&self, app: &mut App) {
         static TILE_SIZE_STAGE: &str = "tile_size_stage";
-        app.add_system_set(
-            SystemSet::on_enter(GameState::PreparingForGame).with_system(spawn_tiles),
-        )
-        .add_startup_system_to_stage(
-            StartupStage::Startup,
-            add_tile_size_res.after(update_window_scale),
-        )
-        .add_system(position_sprites)
-        .add_system(load_card_sprites);
+        app.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_tiles))
+            .add_startup_system_to_stage(
+                StartupStage::Startup,
+                add_tile_size_res.after(update_window_scale),
+            )
+            .add_system(position_sprites)
+            .add_system(load_card_sprites);
     }
 }
 
@@ -64,6 +63,21 @@ fn load_card_sprites(
     commands.insert_resource(CardSprites(atlas_handle, card_sprite_map));
 }
 
+// Where the middle of a tile is, with the board in the middle of the screen.
+pub fn tile_position(map: &Map, tile_size: f32, (x, y): (i32, i32)) -> Vec2 {
+    Vec2::new(
+        (x as f32 - (map.width() - 1) as f32 / 2.) * tile_size,
+        ((map.height() - 1) as f32 / 2. - y as f32) * tile_size,
+    )
+}
+
+// The tile under a point in the world, if it's on the board.
+pub fn tile_at(map: &Map, tile_size: f32, position: Vec2) -> Option<(i32, i32)> {
+    let x = (position.x / tile_size + map.width() as f32 / 2.).floor() as i32;
+    let y = (map.height() as f32 / 2. - position.y / tile_size).floor() as i32;
+    map.terrain(x, y).map(|_| (x, y))
+}
+
 fn position_sprites(
     mut query: Query<(
         Entity,
@@ -73,23 +87,19 @@ fn position_sprites(
         Option<&MovementAnimation>,
         Option<&AttackAnimation>,
     )>,
-    windows: Res<Windows>,
     tile_size: Res<TileSize>,
+    board_map: Res<BoardMap>,
     _is_player_1: Res<IsPlayer1>,
     mut commands: Commands,
 ) {
-    let window = windows.get_primary().unwrap();
-    // parentheses only for clarity (they are unnecessary)
-    let start_y = (window.height() / 2.) - (tile_size.0 / 2.);
-    let start_x = (-window.width() / 2.) + (tile_size.0 / 2.) + (window.width() / 3.);
-
     for (entity, mut transform, card_entity, instant_move_opt, _move_anim_opt, atck_anim_opt) in
         query.iter_mut()
     {
         transform.translation.z = 500.;
-        let target = Vec2::new(
-            start_x + (card_entity.get_x_pos() as f32 * tile_size.0),
-            start_y - (card_entity.get_y_pos() as f32 * tile_size.0),
+        let target = tile_position(
+            &board_map.0,
+            tile_size.0,
+            (card_entity.get_x_pos(), card_entity.get_y_pos()),
         );
         if instant_move_opt.is_some() {
             transform.translation.x = target.x;
@@ -106,67 +116,75 @@ fn position_sprites(
 #[derive(Component)]
 pub struct Tile;
 
+// A checkerboard, in each terrain's colours. Goals are tinted like the troops
+// of the player who wins there.
+fn tile_color(map: &Map, at: (i32, i32), terrain: Terrain) -> Color {
+    let (even, odd) = match terrain {
+        Terrain::Wall => ("5c5c5c", "545454"),
+        Terrain::Water => ("8ecae6", "86c2df"),
+        Terrain::Forest => ("6fa86e", "67a066"),
+        Terrain::Shrine => ("f2d98c", "eed287"),
+        _ if map.is_goal(at, true) => ("d5e9f3", "cde4f0"),
+        _ if map.is_goal(at, false) => ("f6e0e2", "f2d8db"),
+        _ => ("f2f2f2", "ffffff"),
+    };
+    Color::hex(if (at.0 + at.1) % 2 == 0 { even } else { odd }).unwrap()
+}
+
 fn spawn_tiles(
     mut commands: Commands,
-    windows: Res<Windows>,
     tile_size: Res<TileSize>,
+    board_map: Res<BoardMap>,
     asset_server: Res<AssetServer>,
+    mut projection_q: Query<&mut OrthographicProjection, With<MainCamera>>,
 ) {
-    let window = windows.get_primary().unwrap();
-    let one_third_window = window.width() / 3.;
-    let start_y = (window.height() / 2.) - (tile_size.0 / 2.);
-    let start_x = (-window.width() / 2.) + (tile_size.0 / 2.);
-    for i in 0..5 {
-        for j in 0..9 {
-            commands
+    let map = &board_map.0;
+    // the board has the 5x9 tiles between the side panels, bigger maps are
+    // zoomed out to fit
+    projection_q.single_mut().scale = (map.width() as f32 / 5.)
+        .max(map.height() as f32 / 9.)
+        .max(1.);
+    for ((i, j), terrain) in map.tiles() {
+        if terrain == Terrain::Blocked {
+            continue;
+        }
+        let position = tile_position(map, tile_size.0, (i, j));
+        commands
+            .spawn(SpriteBundle {
+                transform: Transform::from_xyz(position.x, position.y, 0.),
+                sprite: Sprite {
+                    custom_size: Some(Vec2::splat(tile_size.0)),
+                    color: tile_color(map, (i, j), terrain),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Tile);
+        for l in 0..2 {
+            let spawned_entity = commands
                 .spawn(SpriteBundle {
-                    transform: Transform::from_xyz(
-                        start_x + one_third_window + (i as f32 * tile_size.0),
-                        start_y - (j as f32 * tile_size.0),
-                        0.,
-                    ),
+                    transform: Transform::from_xyz(position.x, position.y, 250.),
                     sprite: Sprite {
-                        custom_size: Some(Vec2::splat(tile_size.0)),
-                        color: if (i + (j * 5)) % 2 == 0 {
-                            Color::hex("f2f2f2").unwrap()
-                        } else {
-                            Color::hex("ffffff").unwrap()
-                        },
+                        custom_size: Some(Vec2::splat(
+                            tile_size.0 * if l == 1 { 0.80 } else { 1.0 },
+                        )),
                         ..Default::default()
                     },
+                    visibility: Visibility { is_visible: false },
+                    texture: asset_server.load(if l == 1 {
+                        "move_indicator.png"
+                    } else {
+                        "attack_indicator.png"
+                    }),
                     ..Default::default()
                 })
-                .insert(Tile);
-            for l in 0..2 {
-                let spawned_entity = commands
-                    .spawn(SpriteBundle {
-                        transform: Transform::from_xyz(
-                            start_x + one_third_window + (i as f32 * tile_size.0),
-                            start_y - (j as f32 * tile_size.0),
-                            250.,
-                        ),
-                        sprite: Sprite {
-                            custom_size: Some(Vec2::splat(
-                                tile_size.0 * if l == 1 { 0.80 } else { 1.0 },
-                            )),
-                            ..Default::default()
-                        },
-                        visibility: Visibility { is_visible: false },
-                        texture: asset_server.load(if l == 1 {
-                            "move_indicator.png"
-                        } else {
-                            "attack_indicator.png"
-                        }),
-                        ..Default::default()
-                    })
-                    .id();
-                if l == 1 {
-                    commands.entity(spawned_entity).insert(MoveIndicator(i, j));
-                } else {
-                    commands
-                        .entity(spawned_entity)
-                        .insert(AttackIndicator(i, j, 0.));
-                }
+                .id();
+            if l == 1 {
+                commands.entity(spawned_entity).insert(MoveIndicator(i, j));
+            } else {
+                commands
+                    .entity(spawned_entity)
+                    .insert(AttackIndicator(i, j, 0.));
             }
         }
     }
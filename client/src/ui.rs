@@ -6,19 +6,21 @@ use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use crate::{
     card_interactions::ViewingCardEntity,
     currency::{Pawns, Spirits},
-    net::{self, QueueOut},
+    net::{self, ConnectionEvent, QueueOut},
     tilemap::{CardSprites, Tile, TileSize},
     utils, Deck, GameState, IsPlayer1, IsSelfTurn,
 };
 
-use common::{card::Card, messages::ClientMessage};
+use common::{card::Card, codec::Codec, messages::ClientMessage};
 use std::time::Duration;
 
 pub mod before_game;
+pub mod connection_status;
 pub mod in_game_ui;
 pub mod settings;
 
 use before_game::BeforeGamePlugin;
+use connection_status::{ConnectionStatus, ConnectionStatusPlugin};
 use in_game_ui::InGameUiPlugin;
 use settings::{Settings, SettingsUiPlugin};
 
@@ -48,6 +50,7 @@ impl Plugin for UiPlugin {
             .add_plugin(EguiPlugin)
             .add_plugin(InGameUiPlugin)
             .add_plugin(BeforeGamePlugin)
+            .add_plugin(ConnectionStatusPlugin)
             .add_plugin(BellyPlugin)
             .add_plugin(SettingsUiPlugin);
     }
@@ -171,6 +174,7 @@ fn waiting_ui(
 fn main_menu_buttons(
     mut state: ResMut<State<GameState>>,
     settings: Res<Settings>,
+    mut status: ResMut<ConnectionStatus>,
     mut commands: Commands,
     mut elements: Elements,
     mut reader: EventReader<BtnEvent>,
@@ -179,17 +183,23 @@ fn main_menu_buttons(
         match *event {
             BtnEvent::Pressed(entity) => {
                 if let Some(play_btn_ent) = elements.select("#play-button").entities().get(0) {
-                    if play_btn_ent == &entity {
-                        match net::init(&mut commands, &settings.server_addr) {
-                            Ok(_) => {
-                                elements.select(".mm-center-box").remove();
-                                state.set(GameState::PreparingForGame).unwrap();
-                            }
-                            Err(e) => {
-                                elements.select(".conn-err-text").remove_class("hidden");
-                                bevy::log::error!("Could not connect to the server: {}", e);
-                            }
-                        }
+                    // one attempt at a time, the menu moves on once it connects
+                    if play_btn_ent == &entity && !status.is_connecting() {
+                        // JSON stays readable in packet captures while debugging
+                        let codec = if settings.debug_mode {
+                            Codec::Json
+                        } else {
+                            Codec::Bincode
+                        };
+                        elements.select(".conn-err-text").add_class("hidden");
+                        status.0 = Some(ConnectionEvent::Connecting);
+                        net::connect(
+                            &mut commands,
+                            &settings.server_addr,
+                            codec,
+                            settings.tls,
+                            &settings.tls_certificate,
+                        );
                     }
                 }
                 if let Some(settings_btn_ent) =
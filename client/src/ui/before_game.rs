@@ -114,14 +114,10 @@ fn send_deck_packet(
     queue_out: ResMut<QueueOut>,
     mut commands: Commands,
 ) {
-    queue_out
-        .0
-        .lock()
-        .unwrap()
-        .push_back(ClientMessage::PlayerInfo(
-            settings.username.clone(),
-            settings.deck.clone(),
-        ));
+    queue_out.send(ClientMessage::PlayerInfo(
+        settings.username.clone(),
+        settings.deck.clone(),
+    ));
     commands.insert_resource(Deck(settings.deck.clone()));
 }
 
@@ -0,0 +1,102 @@
+use super::*;
+use crate::net::packet_handler::LeaveGame;
+
+pub struct ConnectionStatusPlugin;
+
+impl Plugin for ConnectionStatusPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ConnectionStatus(None))
+            .add_system(track_connection)
+            .add_system(connection_overlay.after(track_connection));
+    }
+}
+
+// The last thing we heard about the connection, `None` once it was dismissed.
+#[derive(Resource)]
+pub struct ConnectionStatus(pub(crate) Option<ConnectionEvent>);
+
+impl ConnectionStatus {
+    pub fn is_connecting(&self) -> bool {
+        matches!(
+            self.0,
+            Some(ConnectionEvent::Connecting) | Some(ConnectionEvent::Reconnecting { .. })
+        )
+    }
+}
+
+fn track_connection(
+    mut reader: EventReader<ConnectionEvent>,
+    mut status: ResMut<ConnectionStatus>,
+    mut state: ResMut<State<GameState>>,
+    mut elements: Elements,
+    mut leave_game: EventWriter<LeaveGame>,
+) {
+    for event in reader.iter() {
+        match event {
+            ConnectionEvent::Connected => match state.current() {
+                GameState::Waiting => {
+                    elements.select(".mm-center-box").remove();
+                    state.set(GameState::PreparingForGame).unwrap();
+                }
+                // after a reconnect the server doesn't know us anymore, and a
+                // game in progress was lost: wait for an opponent again
+                GameState::PreparingForGame => {
+                    state.restart().unwrap();
+                }
+                GameState::Playing => {
+                    leave_game.send(LeaveGame { rejoin_queue: true });
+                }
+                _ => {}
+            },
+            ConnectionEvent::Failed(_) => {
+                elements.select(".conn-err-text").remove_class("hidden");
+            }
+            _ => {}
+        }
+        status.0 = Some(event.clone());
+    }
+}
+
+fn connection_overlay(
+    mut context: ResMut<EguiContext>,
+    mut status: ResMut<ConnectionStatus>,
+    state: Res<State<GameState>>,
+    mut leave_game: EventWriter<LeaveGame>,
+) {
+    let event = match &status.0 {
+        Some(ConnectionEvent::Connected) | None => return,
+        Some(event) => event.clone(),
+    };
+    egui::Area::new("connection-status")
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0., 10.))
+        .show(context.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| match event {
+                ConnectionEvent::Connecting => {
+                    ui.label("Connecting...");
+                }
+                ConnectionEvent::Reconnecting {
+                    attempt,
+                    max_attempts,
+                } => {
+                    ui.label(format!("Reconnecting ({}/{})...", attempt, max_attempts));
+                }
+                // reconnecting can fail outside of the main menu too
+                ConnectionEvent::Failed(reason) if *state.current() == GameState::Waiting => {
+                    ui.label(format!("Could not connect: {}", reason));
+                    if ui.button("Dismiss").clicked() {
+                        status.0 = None;
+                    }
+                }
+                ConnectionEvent::Failed(reason) | ConnectionEvent::Lost(reason) => {
+                    ui.label(format!("Connection lost: {}", reason));
+                    if ui.button("Back to Main Menu").clicked() {
+                        leave_game.send(LeaveGame {
+                            rejoin_queue: false,
+                        });
+                        status.0 = None;
+                    }
+                }
+                ConnectionEvent::Connected => {}
+            });
+        });
+}
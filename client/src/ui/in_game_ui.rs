@@ -1,7 +1,9 @@
 use crate::utils::uppercase_first_letter;
+use crate::{tilemap, BoardMap, GameRules};
 
 use super::*;
-use common::card::{CardEntity, CardNameToSprite};
+use common::card::CardNameToSprite;
+use common::status::Status;
 
 pub struct InGameUiPlugin;
 
@@ -41,7 +43,9 @@ struct CurrentCardDamageLabel;
 #[derive(Component, Default)]
 struct CurrentCardHealthLabel;
 #[derive(Component, Default)]
-struct CurrentCardStunnedLabel;
+struct CurrentCardStatusLabel;
+#[derive(Component, Default)]
+struct CurrentCardDefenseLabel;
 #[derive(Component, Default)]
 struct CurrentCardAbilitiesLabel;
 #[derive(Component, Default)]
@@ -198,10 +202,16 @@ fn spawn_in_game_ui(
                             id="current-card-hp">
                         </label>
                         <label
-                            value="Stunned: "
+                            value="Defense: "
+                            s:font-size=text_size.clone()
+                            with=CurrentCardDefenseLabel
+                            id="current-card-defense">
+                        </label>
+                        <label
+                            value="Status: "
                             s:font-size=text_size.clone()
-                            with=CurrentCardStunnedLabel
-                            id="current-card-stunned">
+                            with=CurrentCardStatusLabel
+                            id="current-card-status">
                         </label>
                          <label
                             value="Abilities: "
@@ -286,11 +296,7 @@ fn chat_ui(
         if let BtnEvent::Pressed(entity) = event{
             if chat_send_btn_ent == *entity{
                 let mut text_box = text_box_query.single_mut();
-                queue_out
-                    .0
-                    .lock()
-                    .unwrap()
-                    .push_back(ClientMessage::ChatMessage(text_box.value.clone()));
+                queue_out.send(ClientMessage::ChatMessage(text_box.value.clone()));
                 text_box.value = "".to_string();
             }
         }
@@ -308,10 +314,10 @@ fn update_current_card_text(
             Without<CurrentCardDamageLabel>
         )
     >,
-    mut stunned: Query<
+    mut status: Query<
         &mut Label, 
         (
-            With<CurrentCardStunnedLabel>, 
+            With<CurrentCardStatusLabel>, 
             Without<CurrentCardHealthLabel>, 
             Without<CurrentCardDamageLabel>, 
             Without<CurrentCardLabel>
@@ -321,7 +327,18 @@ fn update_current_card_text(
         &mut Label,
         (
             With<CurrentCardAbilitiesLabel>,
-            Without<CurrentCardStunnedLabel>,
+            Without<CurrentCardStatusLabel>,
+            Without<CurrentCardHealthLabel>,
+            Without<CurrentCardDamageLabel>,
+            Without<CurrentCardLabel>,
+        )
+    >,
+    mut defense: Query<
+        &mut Label,
+        (
+            With<CurrentCardDefenseLabel>,
+            Without<CurrentCardAbilitiesLabel>,
+            Without<CurrentCardStatusLabel>,
             Without<CurrentCardHealthLabel>,
             Without<CurrentCardDamageLabel>,
             Without<CurrentCardLabel>,
@@ -333,11 +350,25 @@ fn update_current_card_text(
         let card_name = uppercase_first_letter(card_entity.get_card().get_name());
         name.single_mut().value = format!("Current Card: {}", card_name);
         damage.single_mut().value = format!("Damage: {}", card_entity.get_card().get_damage());
-        hp.single_mut().value = format!("Health: {}", card_entity.current_hp);
-        stunned.single_mut().value = if card_entity.stun_count > 0 {
-            "Stunned: Yes".to_string()
-        }else{
-            "Stunned: No".to_string()
+        hp.single_mut().value = format!(
+            "Health: {}/{}",
+            card_entity.current_hp,
+            card_entity.get_card().get_max_hp()
+        );
+        defense.single_mut().value = format!(
+            "Shield: {}  Armor: {}",
+            card_entity.get_shield(),
+            card_entity.get_armor()
+        );
+        let statuses: Vec<String> = card_entity
+            .get_statuses()
+            .iter()
+            .map(|effect| effect.describe())
+            .collect();
+        status.single_mut().value = if statuses.is_empty() {
+            "Status: None".to_string()
+        } else {
+            format!("Status: {}", statuses.join(", "))
         };
         let card_abilities = card_entity.get_card().get_abilities();
         let mut stringified = "Abilities: ".to_string();
@@ -358,49 +389,32 @@ fn in_game_ui_left_panel(
     tile_size: Res<TileSize>,
     mut commands: Commands,
     mut is_placing: ResMut<CurrentlyPlacing>,
-    mut card_entity_q: Query<&mut CardEntity, Without<Label>>,
     mut editing_message: ResMut<EditingMessage>,
     mut elements: Elements,
     mut reader: EventReader<BtnEvent>,
     ui_card_button_elements: Res<UiCardElementList>,
+    board_map: Res<BoardMap>,
 ) {
     for event in reader.iter(){
         if let BtnEvent::Pressed(entity) = event{
             if let Some(button_ent) = elements.select("#end-turn-button").entities().get(0) {
                 if button_ent == entity{
                     if elements.select(".win").entities().len() == 0{
-                        queue_out
-                            .0
-                            .lock()
-                            .unwrap()
-                            .push_back(ClientMessage::EndTurn);
+                        queue_out.send(ClientMessage::EndTurn);
                         is_self_turn.0 = false;
-                        for mut card_entity in card_entity_q.iter_mut() {
-                            if card_entity.is_owned_by_p1() != is_player_1.0 {
-                                card_entity.reset();
-                            }
-                        }
                         elements.select("#end-turn-button").remove();
                         commands.add(|world: &mut World|{
                             world.query_filtered::<&mut Label, With<TurnIndicator>>().single_mut(world).value = "Opponent's Turn".to_string();
                         });
                     }
                     else if let Some(card_entity) = &selected_card.0{
-                        let y = if is_player_1.0 {
-                            card_entity.get_y_pos()
-                        } else {
-                            8 - card_entity.get_y_pos()
-                        };
-                        let x = if is_player_1.0 {
-                            card_entity.get_x_pos()
+                        let at = (card_entity.get_x_pos(), card_entity.get_y_pos());
+                        let (x, y) = if is_player_1.0 {
+                            at
                         } else {
-                            4 - card_entity.get_x_pos()
+                            board_map.0.flip(at)
                         };
-                        queue_out
-                            .0
-                            .lock()
-                            .unwrap()
-                            .push_back(ClientMessage::WinGame(x, y));
+                        queue_out.send(ClientMessage::WinGame(x, y));
                     }
                 }
             
@@ -441,11 +455,11 @@ fn in_game_ui_left_panel(
         } 
     }
     if let Some(card_entity) = selected_card.0.clone() {
-        if card_entity.get_y_pos() == 0 {
+        if board_map.0.is_goal((card_entity.get_x_pos(), card_entity.get_y_pos()), true) {
             if !card_entity.has_moved()
                 && card_entity.is_owned_by_p1() == is_player_1.0
                 && is_self_turn.0
-                && !(card_entity.stun_count > 0)
+                && !card_entity.has_status(Status::Stun)
             {
                 elements.select("#end-turn-button").add_class("win");
                 commands.add(|world: &mut World|{
@@ -497,11 +511,7 @@ fn in_game_ui_left_panel(
             ui.horizontal(|ui| {
                 ui.text_edit_singleline(&mut editing_message.0);
                 if ui.button("Send").clicked() {
-                    queue_out
-                        .0
-                        .lock()
-                        .unwrap()
-                        .push_back(ClientMessage::ChatMessage(editing_message.0.clone()));
+                    queue_out.send(ClientMessage::ChatMessage(editing_message.0.clone()));
                     editing_message.0 = "".to_owned();
                 }
             });
@@ -530,9 +540,9 @@ fn placing_troop(
     tile_size: Res<TileSize>,
     mut commands: Commands,
     mut is_placing: ResMut<CurrentlyPlacing>,
-    mut pawn_count: ResMut<Pawns>,
-    mut spirit_count: ResMut<Spirits>,
     is_player_1: Res<IsPlayer1>,
+    rules: Res<GameRules>,
+    board_map: Res<BoardMap>,
 ) {
     let (camera, global_transform) = cam_query.single();
     let window = windows.get_primary().unwrap();
@@ -551,37 +561,28 @@ fn placing_troop(
                 }
 
                 if mouse.just_pressed(MouseButton::Left) {
-                    let mut x = pos.x;
-                    let mut y = pos.y;
-                    if x < tile_size.0 * 5.0 || x > tile_size.0 * 10.0 {
-                        return;
-                    }
-                    x -= tile_size.0 * 5.0;
-                    x -= x % tile_size.0;
-                    y -= y % tile_size.0;
-                    x /= tile_size.0;
-                    y /= tile_size.0;
-                    if y > 3.0 {
-                        return;
-                    }
-                    if is_player_1.0 {
-                        y = 8.0 - y;
+                    let at = match tilemap::tile_at(&board_map.0, tile_size.0, world_pos) {
+                        Some(at) => at,
+                        None => return,
+                    };
+                    let (x, y) = if is_player_1.0 {
+                        at
                     } else {
-                        x = 4.0 - x;
+                        board_map.0.flip(at)
+                    };
+                    if !rules.0.may_spawn_on((x, y), is_player_1.0)
+                        || !rules.0.map.passable(x, y, currently_placing_card.0.flies())
+                    {
+                        return;
                     }
-                    queue_out
-                        .0
-                        .lock()
-                        .unwrap()
-                        .push_back(ClientMessage::SpawnCard(
-                            currently_placing_card.0.clone(),
-                            x as i32,
-                            y as i32,
-                        ));
+                    queue_out.send(ClientMessage::SpawnCard(
+                        currently_placing_card.0.clone(),
+                        x,
+                        y,
+                    ));
                     commands.entity(entity).despawn();
                     is_placing.0 = false;
-                    pawn_count.0 -= 1;
-                    spirit_count.0 -= currently_placing_card.0.get_cost();
+                    // the server answers with the pawns and spirits left
                     return;
                 }
             }
@@ -2,7 +2,7 @@ use bevy::log::{error, info};
 use bevy_pkv::PkvStore;
 use std::{error::Error, fs, path::Path};
 
-use crate::DevMode;
+use crate::{net::tls::TlsMode, DevMode};
 
 use super::*;
 
@@ -42,13 +42,19 @@ pub struct Settings {
     pub volume: u8,
     pub window_scale: u8,
     pub deck: Vec<Card>,
+    // defaulted so settings saved by older versions still load
+    #[serde(default)]
+    pub tls: TlsMode,
+    // PEM file with an extra certificate to trust, empty for none
+    #[serde(default)]
+    pub tls_certificate: String,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             username: "Player".to_owned(),
-            server_addr: "127.0.0.1:1000".to_owned(),
+            server_addr: "127.0.0.1:7777".to_owned(),
             debug_mode: false,
             volume: 100,
             window_scale: 4,
@@ -59,6 +65,8 @@ impl Default for Settings {
                 "spider".into(),
                 "crow".into(),
             ],
+            tls: TlsMode::Off,
+            tls_certificate: String::new(),
         }
     }
 }
@@ -91,6 +99,15 @@ struct BackgroundImage;
 struct UsernameTextBox;
 #[derive(Component, Default)]
 struct ServerAddressTextBox;
+#[derive(Component, Default)]
+struct TlsCertificateTextBox;
+#[derive(Component, Default)]
+struct TlsModeLabel;
+
+#[derive(Component, Default)]
+struct TlsButton {
+    mode: TlsMode,
+}
 
 fn remove_bg_image(mut commands: Commands, query: Query<Entity, With<BackgroundImage>>) {
     commands.entity(query.single()).despawn();
@@ -125,6 +142,9 @@ fn setup_settings_ui(
     let switch_img = commands.spawn_empty().id();
     let username = settings.username.clone();
     let server_addr = settings.server_addr.clone();
+    let tls_certificate = settings.tls_certificate.clone();
+    let tls_button = TlsButton { mode: settings.tls };
+    let tls_label = settings.tls.label();
     commands.add(eml! {
         <body>
             <img src="ui_bg.png" mode="fit" with=BackgroundImage>
@@ -167,6 +187,23 @@ fn setup_settings_ui(
                                 with=ServerAddressTextBox>
                             </textinput>
                         </img>
+                        // TLS certificate text
+                        <label
+                            value="Trusted Certificate"
+                            s:font-size=text_size
+                            c:s-third-text>
+                        </label>
+                        // TLS certificate text box
+                        <img
+                            src="text_box_bg.png"
+                            mode="fit"
+                            c:s-tls-cert-tb-img>
+                            <textinput
+                                s:font-size=text_size
+                                value=tls_certificate
+                                with=TlsCertificateTextBox>
+                            </textinput>
+                        </img>
                         // Save button
                         <button
                             c:s-bottom-button
@@ -225,6 +262,29 @@ fn setup_settings_ui(
                             <img {switch_img} src=switch_handle_on.clone_weak()>
                             </img>
                         </button>
+                        // Encryption text
+                        <label
+                            value="Encryption"
+                            s:font-size=text_size
+                            c:s-second-text>
+                        </label>
+                        // Encryption mode button, cycles through the modes
+                        <button
+                            id="tls-button"
+                            with=tls_button
+                            c:s-tls-button
+                            s:width=format!("{}px", tile_size * 3.6)
+                            s:height=format!("{}px", tile_size * 0.9)
+                        >
+                            <img src=button_handle.clone_weak() mode="fit">
+                                <label
+                                    with=TlsModeLabel
+                                    s:font-size=text_size
+                                    value=tls_label
+                                >
+                                </label>
+                            </img>
+                        </button>
                         // Back button
                         <button
                             c:s-bottom-button
@@ -253,6 +313,9 @@ fn settings_ui(
     username_input_q: Query<&TextInput, (With<UsernameTextBox>, Without<ServerAddressTextBox>)>,
     server_addr_input_q: Query<&TextInput, (With<ServerAddressTextBox>, Without<UsernameTextBox>)>,
     switch_input_q: Query<&Switch, (Without<ServerAddressTextBox>, Without<UsernameTextBox>)>,
+    tls_certificate_input_q: Query<&TextInput, With<TlsCertificateTextBox>>,
+    mut tls_button_q: Query<&mut TlsButton>,
+    mut tls_label_q: Query<&mut Label, With<TlsModeLabel>>,
 ) {
     for event in reader.iter() {
         if let BtnEvent::Pressed(entity) = event {
@@ -269,6 +332,9 @@ fn settings_ui(
                         .value
                         .trim()
                         .replace(|c: char| !c.is_ascii() || c.is_whitespace(), "");
+                    settings.tls = tls_button_q.single().mode;
+                    settings.tls_certificate =
+                        tls_certificate_input_q.single().value.trim().to_owned();
                     match pkv.set("settings", &settings.clone()) {
                         Ok(_) => {
                             elements.select("#successful-save").remove_class("hidden");
@@ -281,6 +347,13 @@ fn settings_ui(
                     }
                 }
             }
+            if let Some(tls_btn_ent) = elements.select("#tls-button").entities().get(0) {
+                if tls_btn_ent == entity {
+                    let mut tls_button = tls_button_q.single_mut();
+                    tls_button.mode = tls_button.mode.next();
+                    tls_label_q.single_mut().value = tls_button.mode.label().to_owned();
+                }
+            }
             if let Some(back_btn_ent) = elements.select("#back-button").entities().get(0) {
                 if back_btn_ent == entity {
                     state.set(GameState::Waiting).unwrap();
@@ -0,0 +1,286 @@
+use crate::card::{Card, CardAbility, CardEntity};
+use crate::map::Map;
+use crate::status::Status;
+
+// Steps between two tiles when diagonal steps count as one, so every tile
+// around a troop is 1 away.
+pub fn distance(from: (i32, i32), to: (i32, i32)) -> i32 {
+    (from.0 - to.0).abs().max((from.1 - to.1).abs())
+}
+
+// Rounds `numerator / denominator` to the nearest tile, or both tiles when
+// it's exactly halfway between.
+fn nearest_tiles(numerator: i32, denominator: i32) -> (i32, i32) {
+    let (tile, rest) = (
+        numerator.div_euclid(denominator),
+        numerator.rem_euclid(denominator),
+    );
+    match (rest * 2).cmp(&denominator) {
+        std::cmp::Ordering::Less => (tile, tile),
+        std::cmp::Ordering::Equal => (tile, tile + 1),
+        std::cmp::Ordering::Greater => (tile + 1, tile + 1),
+    }
+}
+
+// Whether nothing stands on the straight line between the centres of
+// `from` and `to`. A line passing exactly between two tiles is only blocked
+// if both are taken, so the answer is the same from either end and for
+// either player.
+pub fn line_of_sight(
+    from: (i32, i32),
+    to: (i32, i32),
+    is_occupied: impl Fn((i32, i32)) -> bool,
+) -> bool {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let steps = distance(from, to);
+    (1..steps).all(|step| {
+        let (x1, x2) = nearest_tiles(from.0 * steps + dx * step, steps);
+        let (y1, y2) = nearest_tiles(from.1 * steps + dy * step, steps);
+        !(is_occupied((x1, y1)) && is_occupied((x2, y2)))
+    })
+}
+
+// Whether `attacker`, standing on `from`, can hit a troop on `target`.
+// Troops reach the tiles around them; ranged troops reach further, as long
+// as nothing is in the way.
+pub fn in_attack_range(
+    attacker: &Card,
+    from: (i32, i32),
+    target: (i32, i32),
+    is_occupied: impl Fn((i32, i32)) -> bool,
+) -> bool {
+    let distance = distance(from, target);
+    distance >= 1
+        && distance <= attacker.get_attack_range()
+        && line_of_sight(from, target, is_occupied)
+}
+
+// Like `in_attack_range`, but while any of the enemies on `taunting` is in
+// range, only those can be attacked.
+pub fn may_attack(
+    attacker: &Card,
+    from: (i32, i32),
+    target: (i32, i32),
+    taunting: &[(i32, i32)],
+    is_occupied: impl Fn((i32, i32)) -> bool,
+) -> bool {
+    if !in_attack_range(attacker, from, target, &is_occupied) {
+        return false;
+    }
+    taunting.contains(&target)
+        || !taunting
+            .iter()
+            .any(|&tile| in_attack_range(attacker, from, tile, &is_occupied))
+}
+
+// The tiles on the board at most `range` steps from `at`, not counting `at`.
+pub fn tiles_around(map: &Map, at: (i32, i32), range: i32) -> Vec<(i32, i32)> {
+    let mut tiles = Vec::new();
+    for y in at.1 - range..=at.1 + range {
+        for x in at.0 - range..=at.0 + range {
+            if (x, y) != at && map.in_bounds(x, y) {
+                tiles.push((x, y));
+            }
+        }
+    }
+    tiles
+}
+
+const DIRECTIONS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+// The empty tiles `troop`, standing on `from`, can move to this turn. Troops
+// step one tile in any direction and can't pass through other troops, walls
+// or water, unless their abilities or statuses say otherwise.
+pub fn reachable_tiles(
+    map: &Map,
+    troop: &CardEntity,
+    from: (i32, i32),
+    is_occupied: impl Fn((i32, i32)) -> bool,
+) -> Vec<(i32, i32)> {
+    if troop.has_status(Status::Root) {
+        return Vec::new();
+    }
+    let abilities = troop.get_card().get_abilities();
+    let (mut steps, mut flying, mut charge) = (1, false, false);
+    for ability in &abilities {
+        match ability {
+            CardAbility::Immobile => return Vec::new(),
+            CardAbility::Swift { tiles } => steps = *tiles as i32,
+            CardAbility::Flying => flying = true,
+            CardAbility::Charge => charge = true,
+            _ => {}
+        }
+    }
+    if troop.has_status(Status::Slow) {
+        steps = 1;
+    }
+    let can_land = |tile: (i32, i32)| map.passable(tile.0, tile.1, flying) && !is_occupied(tile);
+    let mut reachable = Vec::new();
+    if charge {
+        // straight lines only
+        for (dx, dy) in DIRECTIONS {
+            for step in 1..=steps {
+                let tile = (from.0 + dx * step, from.1 + dy * step);
+                if !map.passable(tile.0, tile.1, flying) || (!flying && is_occupied(tile)) {
+                    break;
+                }
+                if can_land(tile) {
+                    reachable.push(tile);
+                }
+            }
+        }
+    } else {
+        // a step at a time from every tile reached so far
+        let mut frontier = vec![from];
+        let mut seen = vec![from];
+        for _ in 0..steps {
+            let mut next = Vec::new();
+            for (x, y) in frontier {
+                for (dx, dy) in DIRECTIONS {
+                    let tile = (x + dx, y + dy);
+                    if !map.passable(tile.0, tile.1, flying) || seen.contains(&tile) {
+                        continue;
+                    }
+                    if !flying && is_occupied(tile) {
+                        continue;
+                    }
+                    seen.push(tile);
+                    next.push(tile);
+                    if can_land(tile) {
+                        reachable.push(tile);
+                    }
+                }
+            }
+            frontier = next;
+        }
+    }
+    reachable.sort_unstable();
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::StatusEffect;
+
+    #[test]
+    fn line_of_sight_is_blocked_by_troops_in_between() {
+        let blockers = [(2, 3), (1, 6)];
+        let is_occupied = |tile| blockers.contains(&tile);
+        assert!(!line_of_sight((2, 1), (2, 5), is_occupied));
+        assert!(line_of_sight((2, 2), (2, 3), is_occupied));
+        assert!(line_of_sight((0, 0), (4, 4), is_occupied));
+        // halfway between (1, 6) and (2, 6), only one of them is taken
+        assert!(line_of_sight((1, 5), (2, 7), is_occupied));
+        assert!(!line_of_sight((1, 5), (2, 7), |_| true));
+        // the same line seen from player 2's side
+        let map = Map::default();
+        assert!(line_of_sight((3, 3), (2, 1), |tile| {
+            blockers.contains(&map.flip(tile))
+        }));
+    }
+
+    #[test]
+    fn ranged_troops_reach_further() {
+        let skeleton = Card::from("skeleton");
+        let archer = Card::with_abilities("skeleton", vec![CardAbility::Ranged { range: 2 }]);
+        let empty = |_| false;
+        assert!(in_attack_range(&skeleton, (2, 4), (3, 5), empty));
+        assert!(!in_attack_range(&skeleton, (2, 4), (2, 6), empty));
+        assert!(!in_attack_range(&skeleton, (2, 4), (2, 4), empty));
+        assert!(in_attack_range(&archer, (2, 4), (2, 6), empty));
+        assert!(!in_attack_range(&archer, (2, 4), (2, 6), |tile| tile == (2, 5)));
+        assert!(!in_attack_range(&archer, (2, 4), (2, 7), empty));
+    }
+
+    #[test]
+    fn movement_abilities() {
+        // a wall of troops in front of (2, 4), and one on its left
+        let blockers = [(1, 3), (2, 3), (3, 3), (1, 4)];
+        let is_occupied = |tile| blockers.contains(&tile);
+        let map = Map::default();
+        let walker = Card::with_abilities("skeleton", vec![]);
+        let walker = CardEntity::new(&walker, 0, 0, true);
+        assert_eq!(reachable_tiles(&map, &walker, (2, 4), is_occupied).len(), 4);
+        assert_eq!(
+            reachable_tiles(&map, &walker, (0, 0), |_| false),
+            [(0, 1), (1, 0), (1, 1)]
+        );
+
+        let swift = Card::with_abilities("skeleton", vec![CardAbility::Swift { tiles: 2 }]);
+        let swift = CardEntity::new(&swift, 0, 0, true);
+        let tiles = reachable_tiles(&map, &swift, (2, 4), is_occupied);
+        assert!(tiles.contains(&(2, 6)));
+        // around the wall, not through it
+        assert!(tiles.contains(&(4, 3)) && tiles.contains(&(0, 4)));
+        assert!(!tiles.contains(&(2, 2)));
+        assert!(!tiles.contains(&(0, 3)));
+
+        let flying = Card::with_abilities(
+            "skeleton",
+            vec![CardAbility::Swift { tiles: 2 }, CardAbility::Flying],
+        );
+        let flying = CardEntity::new(&flying, 0, 0, true);
+        let tiles = reachable_tiles(&map, &flying, (2, 4), is_occupied);
+        assert!(tiles.contains(&(2, 2)) && tiles.contains(&(0, 3)));
+        assert!(!tiles.contains(&(2, 3)));
+
+        let charge = Card::with_abilities(
+            "skeleton",
+            vec![CardAbility::Swift { tiles: 3 }, CardAbility::Charge],
+        );
+        let charge = CardEntity::new(&charge, 0, 0, true);
+        let tiles = reachable_tiles(&map, &charge, (2, 4), is_occupied);
+        assert!(tiles.contains(&(2, 7)) && tiles.contains(&(4, 6)));
+        assert!(!tiles.contains(&(3, 6)));
+        assert!(!tiles.contains(&(2, 1)));
+
+        let immobile = Card::with_abilities("skeleton", vec![CardAbility::Immobile]);
+        let immobile = CardEntity::new(&immobile, 0, 0, true);
+        assert!(reachable_tiles(&map, &immobile, (2, 4), |_| false).is_empty());
+
+        let mut slowed = flying.clone();
+        slowed.add_status(StatusEffect::new(Status::Slow, 1));
+        assert_eq!(reachable_tiles(&map, &slowed, (2, 4), is_occupied).len(), 4);
+        slowed.add_status(StatusEffect::new(Status::Root, 1));
+        assert!(reachable_tiles(&map, &slowed, (2, 4), is_occupied).is_empty());
+
+        // nothing crosses blocked tiles, not even flying troops
+        let map = Map::try_from(["1...", "####", "...2"].map(String::from).to_vec()).unwrap();
+        assert_eq!(
+            reachable_tiles(&map, &flying, (0, 2), |_| false),
+            [(1, 2), (2, 2)]
+        );
+
+        // flying troops cross water, but not walls
+        let map = Map::try_from(["1...", "~~==", "...2"].map(String::from).to_vec()).unwrap();
+        assert_eq!(reachable_tiles(&map, &walker, (0, 2), |_| false), [(1, 2)]);
+        let tiles = reachable_tiles(&map, &flying, (0, 2), |_| false);
+        assert!(tiles.contains(&(1, 1)) && tiles.contains(&(2, 0)));
+        assert!(!tiles.contains(&(2, 1)));
+    }
+
+    #[test]
+    fn taunting_troops_are_attacked_first() {
+        let skeleton = Card::from("skeleton");
+        let archer = Card::with_abilities("skeleton", vec![CardAbility::Ranged { range: 2 }]);
+        let empty = |_| false;
+        let taunting = [(2, 6)];
+        // out of the skeleton's reach, so it doesn't matter to it
+        assert!(may_attack(&skeleton, (2, 4), (2, 5), &taunting, empty));
+        assert!(!may_attack(&archer, (2, 4), (2, 5), &taunting, empty));
+        assert!(may_attack(&archer, (2, 4), (2, 6), &taunting, empty));
+        // unless it can't be seen
+        let is_occupied = |tile| tile == (2, 5);
+        assert!(may_attack(&archer, (2, 4), (2, 5), &taunting, is_occupied));
+    }
+}
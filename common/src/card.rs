@@ -2,6 +2,8 @@ use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::status::{self, Status, StatusEffect};
+
 #[derive(Resource)]
 pub struct CardCollection(pub HashMap<String, Card>);
 
@@ -45,7 +47,11 @@ impl CardCollection {
                 4.,
                 2.,
                 4,
-                vec![CardAbility::Stun { amount: 2 }],
+                // the old `Stun { amount: 2 }` counted down on both players'
+                // turns, so it also cost its target one turn of its own
+                vec![CardAbility::Inflict {
+                    effect: StatusEffect::new(Status::Stun, 1),
+                }],
             ),
         );
         map.insert(
@@ -94,6 +100,10 @@ impl Card {
             abilities,
         }
     }
+    pub fn get_max_hp(&self) -> f32 {
+        self.hp
+    }
+
     pub fn get_damage(&self) -> f32 {
         self.attack.clone()
     }
@@ -106,6 +116,22 @@ impl Card {
         self.cost.clone()
     }
 
+    // in tiles, see `board::distance`
+    pub fn get_attack_range(&self) -> i32 {
+        self.abilities
+            .iter()
+            .find_map(|ability| match ability {
+                CardAbility::Ranged { range } => Some(*range as i32),
+                _ => None,
+            })
+            .unwrap_or(1)
+    }
+
+    // flying troops cross water, see `Map::passable`
+    pub fn flies(&self) -> bool {
+        self.abilities.contains(&CardAbility::Flying)
+    }
+
     pub fn get_abilities(&self) -> Vec<CardAbility> {
         self.abilities.clone()
     }
@@ -126,6 +152,17 @@ impl From<&str> for Card {
     }
 }
 
+// For tests: a card from the collection with its abilities swapped out.
+#[cfg(any(test, feature = "test-util"))]
+impl Card {
+    pub fn with_abilities(name: &str, abilities: Vec<CardAbility>) -> Card {
+        Card {
+            abilities,
+            ..Card::from(name)
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 enum CardType {
     Troop,
@@ -142,7 +179,9 @@ pub struct CardEntity {
     is_owned_by_p1: bool,
     has_moved: bool,
     has_attacked: bool,
-    pub stun_count: i32,
+    statuses: Vec<StatusEffect>,
+    // put on the board by an ability rather than played from the hand
+    summoned: bool,
 }
 
 impl CardEntity {
@@ -155,7 +194,9 @@ impl CardEntity {
             current_hp: card.hp,
             has_moved: false,
             has_attacked: false,
-            stun_count: 1,
+            // troops can't act on the turn they're played
+            statuses: vec![StatusEffect::new(Status::Stun, 0)],
+            summoned: false,
         }
     }
 
@@ -183,6 +224,14 @@ impl CardEntity {
         }
     }
 
+    pub fn is_summoned(&self) -> bool {
+        self.summoned
+    }
+
+    pub fn set_summoned(&mut self, summoned: bool) {
+        self.summoned = summoned;
+    }
+
     pub fn has_moved(&self) -> bool {
         self.has_moved.clone()
     }
@@ -216,12 +265,92 @@ impl CardEntity {
         self.card.clone()
     }
 
+    // Takes a hit of `damage` and returns how much of it got through. A
+    // shield charge blocks a whole hit, armor takes a fixed amount off each.
+    pub fn take_damage(&mut self, damage: f32) -> f32 {
+        let mut armor = 0.;
+        for ability in self.card.get_abilities_mut() {
+            match ability {
+                CardAbility::Shield { charges } if *charges > 0 => {
+                    *charges -= 1;
+                    return 0.;
+                }
+                CardAbility::Armor { reduction } => armor += *reduction,
+                _ => {}
+            }
+        }
+        let dealt = (damage - armor).max(0.);
+        self.current_hp -= dealt;
+        dealt
+    }
+
+    // Whether the troop hits back after surviving an attack, given it can
+    // reach the attacker. `always` is the retaliation rule, which makes every
+    // troop do it.
+    pub fn retaliates(&self, always: bool) -> bool {
+        self.current_hp > 0.
+            && !self.has_status(Status::Stun)
+            && (always
+                || !self.has_status(Status::Silence)
+                    && self.card.abilities.contains(&CardAbility::Retaliate))
+    }
+
+    // Heals up to the card's full hp and returns how much it healed.
+    pub fn heal(&mut self, amount: f32) -> f32 {
+        let healed = amount.min(self.card.hp - self.current_hp).max(0.);
+        self.current_hp += healed;
+        healed
+    }
+
+    // shield charges left
+    pub fn get_shield(&self) -> u8 {
+        self.card
+            .abilities
+            .iter()
+            .map(|ability| match ability {
+                CardAbility::Shield { charges } => *charges,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    pub fn get_armor(&self) -> f32 {
+        self.card
+            .abilities
+            .iter()
+            .map(|ability| match ability {
+                CardAbility::Armor { reduction } => *reduction,
+                _ => 0.,
+            })
+            .sum()
+    }
+
+    // Raises the troop's attack and hp, max hp included.
+    pub fn buff(&mut self, attack: f32, hp: f32) {
+        self.card.attack += attack;
+        self.card.hp += hp;
+        self.current_hp += hp;
+    }
+
+    pub fn get_statuses(&self) -> Vec<StatusEffect> {
+        self.statuses.clone()
+    }
+
+    pub fn has_status(&self, status: Status) -> bool {
+        self.statuses.iter().any(|effect| effect.status == status)
+    }
+
+    // see `status::apply` for how statuses stack
+    pub fn add_status(&mut self, effect: StatusEffect) {
+        status::apply(&mut self.statuses, effect);
+    }
+
+    // Called as its owner's turn starts. Status damage ignores shields and
+    // armor, the troop may die of it.
     pub fn reset(&mut self) {
         self.has_moved = false;
         self.has_attacked = false;
-        if self.stun_count > 0 {
-            self.stun_count -= 1;
-        }
+        self.current_hp -= status::tick(&mut self.statuses);
         let card = &mut self.card;
         for ability in card.get_abilities_mut() {
             if let CardAbility::MultiAttack {
@@ -239,7 +368,72 @@ impl CardEntity {
 pub enum CardAbility {
     MultiAttack { max_attacks: u8, attack_count: u8 },
     SpiritCollector,
-    Stun { amount: i32 },
+    // gives every troop it attacks `effect`
+    Inflict { effect: StatusEffect },
+    // attacks troops up to `range` tiles away that it can see, and stays put
+    // when one of them dies
+    Ranged { range: u8 },
+    // moves up to `tiles` tiles instead of one
+    Swift { tiles: u8 },
+    // moves over other troops
+    Flying,
+    // moves in straight lines only, as far as it's able to
+    Charge,
+    // can't move at all
+    Immobile,
+    // heals friendly troops within `range` tiles by `amount` at the start of
+    // its owner's turn
+    Heal { amount: f32, range: u8 },
+    // each charge blocks one hit entirely
+    Shield { charges: u8 },
+    // taken off the damage of every hit
+    Armor { reduction: f32 },
+    // heals itself at the start of its owner's turn
+    Regenerate { per_turn: f32 },
+    // strikes back at troops that attack it, if it survives and can reach them
+    Retaliate,
+    // does `effect` whenever `trigger` happens, see `Trigger`
+    Triggered { trigger: Trigger, effect: Effect },
+}
+
+// When a `CardAbility::Triggered` ability goes off. The server resolves them
+// one after another, in the order they were triggered.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    // when the card is played or summoned
+    OnSpawn,
+    OnDeath,
+    // after each of its attacks
+    OnAttack,
+    // at the start and the end of its owner's turns
+    OnTurnStart,
+    OnTurnEnd,
+}
+
+impl Trigger {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Trigger::OnSpawn => "On Spawn",
+            Trigger::OnDeath => "On Death",
+            Trigger::OnAttack => "On Attack",
+            Trigger::OnTurnStart => "On Turn Start",
+            Trigger::OnTurnEnd => "On Turn End",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Effect {
+    // puts up to `count` troops of `card` on the empty tiles around the troop,
+    // or where it died, for its owner
+    Summon { card: String, count: u8 },
+    // hits every enemy within `range` tiles of the troop for `damage`, or of
+    // its target for `Trigger::OnAttack`
+    Splash { damage: f32, range: u8 },
+    // raises the attack and hp of friendly troops within `range` tiles
+    Buff { attack: f32, hp: f32, range: u8 },
+    // gives the troop itself `effect`
+    Gain { effect: StatusEffect },
 }
 
 impl ToString for CardAbility {
@@ -248,8 +442,34 @@ impl ToString for CardAbility {
             Self::MultiAttack { max_attacks, .. } => {
                 format!("Multi-Attack {}", max_attacks + 1).to_string()
             }
-            Self::Stun { .. } => "Stun".to_string(),
+            Self::Inflict { ref effect } => format!("Inflicts {}", effect.describe()),
             Self::SpiritCollector => "Spirit Collector".to_string(),
+            Self::Ranged { range } => format!("Ranged {}", range),
+            Self::Swift { tiles } => format!("Swift {}", tiles),
+            Self::Flying => "Flying".to_string(),
+            Self::Charge => "Charge".to_string(),
+            Self::Immobile => "Immobile".to_string(),
+            Self::Heal { amount, range } => format!("Heal {} (range {})", amount, range),
+            Self::Shield { charges } => format!("Shield {}", charges),
+            Self::Armor { reduction } => format!("Armor {}", reduction),
+            Self::Regenerate { per_turn } => format!("Regenerate {}", per_turn),
+            Self::Retaliate => "Retaliate".to_string(),
+            Self::Triggered {
+                trigger,
+                ref effect,
+            } => {
+                let effect = match effect {
+                    Effect::Summon { card, count } => format!("Summon {} {}", count, card),
+                    Effect::Splash { damage, range } => {
+                        format!("Splash {} (range {})", damage, range)
+                    }
+                    Effect::Buff { attack, hp, range } => {
+                        format!("Buff +{}/+{} (range {})", attack, hp, range)
+                    }
+                    Effect::Gain { effect } => format!("Gains {}", effect.describe()),
+                };
+                format!("{}: {}", trigger.name(), effect)
+            }
         }
     }
 }
@@ -268,3 +488,47 @@ impl Default for CardNameToSprite {
         CardNameToSprite(map)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shields_and_armor_soak_damage() {
+        let skeleton = Card::with_abilities("skeleton", vec![CardAbility::Shield { charges: 1 }]);
+        let mut skeleton = CardEntity::new(&skeleton, 0, 0, true);
+        assert_eq!(skeleton.get_shield(), 1);
+        assert_eq!(skeleton.take_damage(4.), 0.);
+        assert_eq!(skeleton.get_shield(), 0);
+        assert_eq!(skeleton.take_damage(4.), 4.);
+        assert_eq!(skeleton.current_hp, 1.);
+        assert_eq!(skeleton.heal(10.), 4.);
+        assert_eq!(skeleton.current_hp, 5.);
+
+        let kraken = Card::with_abilities("kraken", vec![CardAbility::Armor { reduction: 1. }]);
+        let mut kraken = CardEntity::new(&kraken, 0, 0, true);
+        assert_eq!(kraken.take_damage(3.), 2.);
+        assert_eq!(kraken.take_damage(0.5), 0.);
+        assert_eq!(kraken.current_hp, 10.);
+    }
+
+    #[test]
+    fn only_some_troops_retaliate() {
+        let skeleton = Card::with_abilities("skeleton", vec![CardAbility::Retaliate]);
+        let mut skeleton = CardEntity::new(&skeleton, 0, 0, true);
+        let mut spider = CardEntity::new(&Card::from("spider"), 0, 0, true);
+        // freshly played troops are stunned
+        assert!(!skeleton.retaliates(true));
+        skeleton.reset();
+        spider.reset();
+        assert!(skeleton.retaliates(false));
+        assert!(!spider.retaliates(false));
+        assert!(spider.retaliates(true));
+
+        skeleton.add_status(StatusEffect::new(Status::Silence, 1));
+        assert!(!skeleton.retaliates(false));
+        assert!(skeleton.retaliates(true));
+        spider.take_damage(4.);
+        assert!(!spider.retaliates(true));
+    }
+}
@@ -0,0 +1,241 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::framing::FrameError;
+
+// How packet payloads are encoded on a connection. Every connection starts out
+// speaking JSON: the client's first packet is a `ClientMessage::Handshake`
+// naming the codec it wants, the server answers with a
+// `ServerMessage::Handshake` (still in JSON) and both sides switch afterwards.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Codec {
+    // human readable, handy when debugging with a packet capture
+    #[default]
+    Json,
+    Bincode,
+}
+
+impl Codec {
+    pub fn encode<T: Serialize>(&self, message: &T) -> Result<Vec<u8>, FrameError> {
+        match self {
+            Self::Json => serde_json::to_vec(message).map_err(|e| FrameError::Malformed(e.into())),
+            Self::Bincode => bincode::serialize(message).map_err(|e| FrameError::Malformed(e)),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, FrameError> {
+        match self {
+            Self::Json => {
+                serde_json::from_slice(payload).map_err(|e| FrameError::Malformed(e.into()))
+            }
+            Self::Bincode => bincode::deserialize(payload).map_err(|e| FrameError::Malformed(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        card::{Card, CardEntity, Trigger},
+        messages::{AbilityEvent, AttackResult, ClientMessage, ServerMessage},
+        rules::RuleSet,
+        status::{Status, StatusEffect},
+    };
+
+    // Both matches are exhaustive on purpose: a new message variant won't
+    // compile until it gets an index here and an instance in the lists below.
+    fn server_variant(message: &ServerMessage) -> usize {
+        match message {
+            ServerMessage::Handshake(_) => 0,
+            ServerMessage::StartGame(..) => 1,
+            ServerMessage::StartTurn => 2,
+            ServerMessage::SpawnCard(_) => 3,
+            ServerMessage::MoveTroop(..) => 4,
+            ServerMessage::AttackResult(_) => 5,
+            ServerMessage::EndGame(_) => 6,
+            ServerMessage::ChatMessage(_) => 7,
+            ServerMessage::TurnTimedOut => 8,
+            ServerMessage::Ping(_) => 9,
+            ServerMessage::Pong(_) => 10,
+            ServerMessage::Announcement(_) => 11,
+            ServerMessage::ServerShuttingDown { .. } => 12,
+            ServerMessage::ChatRejected(_) => 13,
+            ServerMessage::HpChanged { .. } => 14,
+            ServerMessage::AbilityEvents(_) => 15,
+            ServerMessage::TroopsChanged(_) => 16,
+            ServerMessage::Currency { .. } => 17,
+            ServerMessage::Disconnect(_) => 18,
+        }
+    }
+    const SERVER_VARIANTS: usize = 19;
+
+    fn client_variant(message: &ClientMessage) -> usize {
+        match message {
+            ClientMessage::Handshake(_) => 0,
+            ClientMessage::PlayerInfo(..) => 1,
+            ClientMessage::MoveTroop(..) => 2,
+            ClientMessage::AttackTroop(..) => 3,
+            ClientMessage::SpawnCard(..) => 4,
+            ClientMessage::EndTurn => 5,
+            ClientMessage::WinGame(..) => 6,
+            ClientMessage::ChatMessage(_) => 7,
+            ClientMessage::Resign => 8,
+            ClientMessage::Ping(_) => 9,
+            ClientMessage::Pong(_) => 10,
+        }
+    }
+    const CLIENT_VARIANTS: usize = 11;
+
+    fn server_messages() -> Vec<ServerMessage> {
+        let mut kraken = CardEntity::new(&Card::from("kraken"), 2, 3, false);
+        kraken.attacked();
+        vec![
+            ServerMessage::Handshake(Codec::Bincode),
+            ServerMessage::StartGame(true, RuleSet::default()),
+            ServerMessage::StartGame(
+                false,
+                RuleSet {
+                    retaliation: true,
+                    turn_timer_secs: 30,
+                    ..RuleSet::default()
+                },
+            ),
+            ServerMessage::StartTurn,
+            ServerMessage::TurnTimedOut,
+            ServerMessage::SpawnCard(CardEntity::new(&Card::from("spider"), 4, 8, true)),
+            ServerMessage::SpawnCard(kraken),
+            ServerMessage::MoveTroop(0, 1, 2, 3),
+            ServerMessage::AttackResult(AttackResult {
+                from: (4, 8),
+                to: (3, 7),
+                damage: 2.5,
+                retaliation: Some(0.),
+                attacker: Some(CardEntity::new(&Card::from("crow"), 4, 8, true)),
+                target: Some(CardEntity::new(&Card::from("skeleton"), 3, 7, false)),
+            }),
+            ServerMessage::AttackResult(AttackResult {
+                from: (0, 0),
+                to: (-1, i32::MAX),
+                damage: 6.,
+                retaliation: None,
+                attacker: None,
+                target: None,
+            }),
+            ServerMessage::HpChanged {
+                x: 2,
+                y: 7,
+                hp: 4.5,
+            },
+            ServerMessage::AbilityEvents(vec![
+                AbilityEvent::Triggered {
+                    x: 1,
+                    y: 5,
+                    trigger: Trigger::OnDeath,
+                },
+                AbilityEvent::Summoned(CardEntity::new(&Card::from("skeleton"), 1, 5, true)),
+                AbilityEvent::Damaged {
+                    damage: 1.,
+                    troop: CardEntity::new(&Card::from("crow"), 2, 4, false),
+                },
+                AbilityEvent::Died { x: 2, y: 4 },
+                AbilityEvent::Buffed {
+                    attack: 1.,
+                    hp: 0.,
+                    troop: CardEntity::new(&Card::from("reaper"), 0, 6, true),
+                },
+                AbilityEvent::StatusAdded {
+                    effect: StatusEffect::damaging(Status::Poison, 1.5, 2),
+                    troop: CardEntity::new(&Card::from("spider"), 3, 3, false),
+                },
+            ]),
+            ServerMessage::AbilityEvents(Vec::new()),
+            ServerMessage::TroopsChanged(vec![
+                CardEntity::new(&Card::from("kraken"), 0, 0, true),
+                CardEntity::new(&Card::from("crow"), 4, 8, true),
+            ]),
+            ServerMessage::Currency {
+                pawns: 5,
+                spirits: -1,
+            },
+            ServerMessage::EndGame(false),
+            ServerMessage::ChatMessage("Player: gg".to_owned()),
+            ServerMessage::ChatRejected("You are muted".to_owned()),
+            ServerMessage::Ping(0),
+            ServerMessage::Pong(u64::MAX),
+            ServerMessage::Announcement("Restarting in 5 minutes".to_owned()),
+            ServerMessage::ServerShuttingDown { seconds: 60 },
+            ServerMessage::Disconnect("the server is full, try again later".to_owned()),
+        ]
+    }
+
+    fn client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Handshake(Codec::Json),
+            ClientMessage::PlayerInfo(
+                "Player".to_owned(),
+                vec!["skeleton".into(), "reaper".into(), "crow".into()],
+            ),
+            ClientMessage::MoveTroop(0, 1, 2, 3),
+            ClientMessage::AttackTroop(3, 2, 1, 0),
+            ClientMessage::SpawnCard("kraken".into(), 4, 8),
+            ClientMessage::EndTurn,
+            ClientMessage::WinGame(2, 0),
+            ClientMessage::ChatMessage("héllo".to_owned()),
+            ClientMessage::Resign,
+            ClientMessage::Ping(1_676_000_000_000),
+            ClientMessage::Pong(7),
+        ]
+    }
+
+    #[test]
+    fn every_server_message_round_trips() {
+        let messages = server_messages();
+        let mut seen = [false; SERVER_VARIANTS];
+        for message in &messages {
+            seen[server_variant(message)] = true;
+            for codec in [Codec::Json, Codec::Bincode] {
+                let bytes = codec.encode(message).unwrap();
+                assert_eq!(&codec.decode::<ServerMessage>(&bytes).unwrap(), message);
+            }
+        }
+        assert!(
+            seen.iter().all(|x| *x),
+            "a ServerMessage variant is untested"
+        );
+    }
+
+    #[test]
+    fn every_client_message_round_trips() {
+        let messages = client_messages();
+        let mut seen = [false; CLIENT_VARIANTS];
+        for message in &messages {
+            seen[client_variant(message)] = true;
+            for codec in [Codec::Json, Codec::Bincode] {
+                let bytes = codec.encode(message).unwrap();
+                assert_eq!(&codec.decode::<ClientMessage>(&bytes).unwrap(), message);
+            }
+        }
+        assert!(
+            seen.iter().all(|x| *x),
+            "a ClientMessage variant is untested"
+        );
+    }
+
+    #[test]
+    fn bincode_is_smaller_for_cards() {
+        let message = ServerMessage::SpawnCard(CardEntity::new(&Card::from("kraken"), 0, 0, true));
+        let json = Codec::Json.encode(&message).unwrap();
+        let binary = Codec::Bincode.encode(&message).unwrap();
+        assert!(binary.len() < json.len());
+    }
+
+    #[test]
+    fn garbage_is_malformed() {
+        for codec in [Codec::Json, Codec::Bincode] {
+            assert!(matches!(
+                codec.decode::<ClientMessage>(&[0xff, 0xff, 0xff, 0xff, 0xff]),
+                Err(FrameError::Malformed(_))
+            ));
+        }
+    }
+}
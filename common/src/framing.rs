@@ -0,0 +1,231 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::codec::Codec;
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+};
+
+// Every packet on the wire is a big-endian u32 length followed by that many
+// bytes of payload. Anything longer than this is treated as a broken or
+// hostile peer instead of being allocated.
+pub const MAX_FRAME_SIZE: u32 = 64 * 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+    // the peer closed the connection cleanly, between two frames
+    Eof,
+    Io(io::Error),
+    Oversize(u32),
+    Malformed(Box<dyn Error + Send + Sync>),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Eof => write!(f, "connection closed"),
+            Self::Io(e) => write!(f, "i/o error: {}", e),
+            Self::Oversize(len) => write!(
+                f,
+                "frame of {} bytes exceeds the maximum of {} bytes",
+                len, MAX_FRAME_SIZE
+            ),
+            Self::Malformed(e) => write!(f, "malformed packet: {}", e),
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Malformed(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+pub fn check_frame_len(len: u32) -> Result<(), FrameError> {
+    if len > MAX_FRAME_SIZE {
+        return Err(FrameError::Oversize(len));
+    }
+    Ok(())
+}
+
+// Reads one whole frame, however the bytes were split up by the socket.
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Vec<u8>, FrameError> {
+    let mut len_buf: [u8; 4] = [0; 4];
+    let mut filled = 0;
+    while filled < len_buf.len() {
+        match reader.read(&mut len_buf[filled..]) {
+            Ok(0) if filled == 0 => return Err(FrameError::Eof),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let len = u32::from_be_bytes(len_buf);
+    check_frame_len(len)?;
+    let mut buffer = vec![0; len as usize];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+// Prefixes `payload` with its length. Prefix and payload are kept in one
+// buffer so they go out in a single write and two writers sharing a stream
+// can never interleave halves of their packets.
+pub fn frame(payload: &[u8]) -> Result<Vec<u8>, FrameError> {
+    let len = u32::try_from(payload.len()).unwrap_or(u32::MAX);
+    check_frame_len(len)?;
+    let mut buffer = Vec::with_capacity(payload.len() + 4);
+    buffer.write_u32::<BigEndian>(len)?;
+    buffer.extend_from_slice(payload);
+    Ok(buffer)
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> Result<(), FrameError> {
+    writer.write_all(&frame(payload)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_message<R: Read, T: DeserializeOwned>(
+    reader: &mut R,
+    codec: Codec,
+) -> Result<T, FrameError> {
+    let frame = read_frame(reader)?;
+    codec.decode(&frame)
+}
+
+pub fn write_message<W: Write, T: Serialize>(
+    writer: &mut W,
+    codec: Codec,
+    message: &T,
+) -> Result<(), FrameError> {
+    let payload = codec.encode(message)?;
+    write_frame(writer, &payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{ClientMessage, ServerMessage};
+    use std::io::Cursor;
+
+    // hands out at most `chunk` bytes per call, like a slow socket would
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = (self.pos + self.chunk).min(self.data.len());
+            let n = (end - self.pos).min(buf.len());
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    fn encode(message: &ClientMessage) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_message(&mut out, Codec::Json, message).unwrap();
+        out
+    }
+
+    #[test]
+    fn fragmented_stream() {
+        let sent = ClientMessage::ChatMessage("hello there".to_owned());
+        for chunk in 1..6 {
+            let mut reader = Trickle {
+                data: encode(&sent),
+                pos: 0,
+                chunk,
+            };
+            let received: ClientMessage = read_message(&mut reader, Codec::Json).unwrap();
+            assert_eq!(received, sent);
+            assert!(matches!(
+                read_message::<_, ClientMessage>(&mut reader, Codec::Json),
+                Err(FrameError::Eof)
+            ));
+        }
+    }
+
+    #[test]
+    fn coalesced_stream() {
+        let sent = vec![
+            ClientMessage::MoveTroop(0, 1, 2, 3),
+            ClientMessage::EndTurn,
+            ClientMessage::ChatMessage("gg".to_owned()),
+        ];
+        let mut data = Vec::new();
+        for message in &sent {
+            data.extend(encode(message));
+        }
+        let mut reader = Cursor::new(data);
+        for message in &sent {
+            assert_eq!(
+                &read_message::<_, ClientMessage>(&mut reader, Codec::Json).unwrap(),
+                message
+            );
+        }
+        assert!(matches!(
+            read_message::<_, ClientMessage>(&mut reader, Codec::Json),
+            Err(FrameError::Eof)
+        ));
+    }
+
+    #[test]
+    fn oversize_frame_is_rejected_before_allocating() {
+        let mut reader = Cursor::new(u32::MAX.to_be_bytes().to_vec());
+        assert!(matches!(
+            read_frame(&mut reader),
+            Err(FrameError::Oversize(u32::MAX))
+        ));
+        let payload = vec![0; MAX_FRAME_SIZE as usize + 1];
+        assert!(matches!(
+            write_frame(&mut Vec::new(), &payload),
+            Err(FrameError::Oversize(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_frame_is_an_io_error() {
+        let mut data = encode(&ClientMessage::EndTurn);
+        data.pop();
+        let mut reader = Cursor::new(data);
+        match read_frame(&mut reader) {
+            Err(FrameError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("expected an i/o error, got {:?}", other),
+        }
+        let mut reader = Cursor::new(vec![0, 0]);
+        assert!(matches!(read_frame(&mut reader), Err(FrameError::Io(_))));
+    }
+
+    #[test]
+    fn malformed_json_keeps_the_stream_in_sync() {
+        let mut data = Vec::new();
+        write_frame(&mut data, b"{not json").unwrap();
+        write_message(&mut data, Codec::Json, &ServerMessage::StartTurn).unwrap();
+        let mut reader = Cursor::new(data);
+        assert!(matches!(
+            read_message::<_, ServerMessage>(&mut reader, Codec::Json),
+            Err(FrameError::Malformed(_))
+        ));
+        assert_eq!(
+            read_message::<_, ServerMessage>(&mut reader, Codec::Json).unwrap(),
+            ServerMessage::StartTurn
+        );
+    }
+}
@@ -1,2 +1,8 @@
+pub mod board;
 pub mod card;
+pub mod codec;
+pub mod framing;
+pub mod map;
 pub mod messages;
+pub mod rules;
+pub mod status;
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Terrain {
+    Floor,
+    // not part of the board, nothing can stand on or pass through it
+    Blocked,
+    // on the board, but nothing can stand on or pass through it either
+    Wall,
+    // only flying troops can cross it or stand on it
+    Water,
+    // troops standing in it take less damage
+    Forest,
+    // whoever has a troop on it as their turn starts gets spirits
+    Shrine,
+}
+
+impl Terrain {
+    // taken off every hit on a troop standing on it, like armor
+    pub fn defence(self) -> f32 {
+        match self {
+            Terrain::Forest => 1.,
+            _ => 0.,
+        }
+    }
+}
+
+// The board's layout. Written down as rows of tiles, from the top of player
+// 1's screen:
+//   `.` floor
+//   `#` blocked
+//   `=` wall
+//   `~` water
+//   `^` forest
+//   `*` shrine
+//   `1` floor where player 1 wins by bringing a troop
+//   `2` floor where player 2 wins by bringing a troop
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct Map {
+    width: i32,
+    height: i32,
+    // row by row
+    terrain: Vec<Terrain>,
+    // each goal tile, and whether it's player 1's
+    goals: Vec<((i32, i32), bool)>,
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        let mut rows = vec!["11111".to_owned()];
+        rows.extend(vec![".....".to_owned(); 7]);
+        rows.push("22222".to_owned());
+        Map::try_from(rows).unwrap()
+    }
+}
+
+impl Map {
+    pub const MAX_SIZE: i32 = 15;
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    // `None` off the board
+    pub fn terrain(&self, x: i32, y: i32) -> Option<Terrain> {
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            Some(self.terrain[(y * self.width + x) as usize])
+        } else {
+            None
+        }
+    }
+
+    // Whether the tile is part of the board.
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        matches!(self.terrain(x, y), Some(terrain) if terrain != Terrain::Blocked)
+    }
+
+    // Whether a troop can stand on or move through the tile.
+    pub fn passable(&self, x: i32, y: i32, flying: bool) -> bool {
+        match self.terrain(x, y) {
+            Some(Terrain::Floor | Terrain::Forest | Terrain::Shrine) => true,
+            Some(Terrain::Water) => flying,
+            Some(Terrain::Blocked | Terrain::Wall) | None => false,
+        }
+    }
+
+    pub fn defence(&self, x: i32, y: i32) -> f32 {
+        self.terrain(x, y).map_or(0., Terrain::defence)
+    }
+
+    // Both players see the board from their own side, so the server turns
+    // player 2's coordinates around before using them, and back when telling
+    // them about the board.
+    pub fn flip(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        (self.width - 1 - x, self.height - 1 - y)
+    }
+
+    pub fn is_goal(&self, at: (i32, i32), is_player_1: bool) -> bool {
+        self.goals.contains(&(at, is_player_1))
+    }
+
+    // The map as a player sees it, with their side at the bottom and their
+    // goals counting as player 1's.
+    pub fn seen_by(&self, is_player_1: bool) -> Map {
+        if is_player_1 {
+            return self.clone();
+        }
+        let mut terrain = self.terrain.clone();
+        terrain.reverse();
+        Map {
+            terrain,
+            goals: self
+                .goals
+                .iter()
+                .map(|&(at, player_1)| (self.flip(at), !player_1))
+                .collect(),
+            ..*self
+        }
+    }
+
+    // Every tile with its terrain, row by row.
+    pub fn tiles(&self) -> impl Iterator<Item = ((i32, i32), Terrain)> + '_ {
+        let width = self.width;
+        self.terrain
+            .iter()
+            .enumerate()
+            .map(move |(i, &terrain)| ((i as i32 % width, i as i32 / width), terrain))
+    }
+}
+
+impl TryFrom<Vec<String>> for Map {
+    type Error = String;
+
+    fn try_from(rows: Vec<String>) -> Result<Self, Self::Error> {
+        let height = rows.len() as i32;
+        let width = rows.first().map_or(0, |row| row.chars().count()) as i32;
+        if !(2..=Map::MAX_SIZE).contains(&height) || !(1..=Map::MAX_SIZE).contains(&width) {
+            return Err(format!(
+                "maps have to be between 1x2 and {0}x{0} tiles",
+                Map::MAX_SIZE
+            ));
+        }
+        let mut map = Map {
+            width,
+            height,
+            terrain: Vec::new(),
+            goals: Vec::new(),
+        };
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() as i32 != width {
+                return Err("all rows of a map have to be as long".to_owned());
+            }
+            for (x, tile) in row.chars().enumerate() {
+                let at = (x as i32, y as i32);
+                map.terrain.push(match tile {
+                    '.' => Terrain::Floor,
+                    '#' => Terrain::Blocked,
+                    '=' => Terrain::Wall,
+                    '~' => Terrain::Water,
+                    '^' => Terrain::Forest,
+                    '*' => Terrain::Shrine,
+                    '1' | '2' => {
+                        map.goals.push((at, tile == '1'));
+                        Terrain::Floor
+                    }
+                    _ => return Err(format!("unknown tile '{}' in map", tile)),
+                });
+            }
+        }
+        let has_goal = |is_player_1| map.goals.iter().any(|goal| goal.1 == is_player_1);
+        if !has_goal(true) || !has_goal(false) {
+            return Err("both players need a goal tile on the map".to_owned());
+        }
+        Ok(map)
+    }
+}
+
+impl From<Map> for Vec<String> {
+    fn from(map: Map) -> Self {
+        let mut rows = vec![String::new(); map.height as usize];
+        for ((x, y), terrain) in map.tiles() {
+            rows[y as usize].push(match terrain {
+                Terrain::Blocked => '#',
+                Terrain::Wall => '=',
+                Terrain::Water => '~',
+                Terrain::Forest => '^',
+                Terrain::Shrine => '*',
+                Terrain::Floor if map.is_goal((x, y), true) => '1',
+                Terrain::Floor if map.is_goal((x, y), false) => '2',
+                Terrain::Floor => '.',
+            });
+        }
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(rows: &[&str]) -> Result<Map, String> {
+        Map::try_from(rows.iter().map(|row| row.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn maps_are_written_as_rows() {
+        let default = Map::default();
+        assert_eq!((default.width(), default.height()), (5, 9));
+        assert!(default.is_goal((3, 0), true) && default.is_goal((3, 8), false));
+        assert!(!default.is_goal((3, 8), true));
+
+        let rows = ["#11#", "....", ".##.", "2..2"];
+        let map = parse(&rows).unwrap();
+        assert!(!map.in_bounds(0, 0) && !map.in_bounds(1, 2) && !map.in_bounds(4, 1));
+        assert!(map.in_bounds(1, 0) && map.in_bounds(0, 3));
+        assert_eq!(map.flip((0, 1)), (3, 2));
+        assert_eq!(Vec::<String>::from(map.clone()), rows);
+
+        // player 2 sees it turned around, with their goals on top
+        let seen = map.seen_by(false);
+        assert!(seen.is_goal((0, 0), true) && seen.is_goal((2, 3), false));
+        assert!(!seen.in_bounds(2, 1) && !seen.in_bounds(3, 3));
+        assert_eq!(Vec::<String>::from(seen), ["1..1", ".##.", "....", "#22#"]);
+
+        let map = parse(&["1.1", "=~^", ".*.", "2.2"]).unwrap();
+        assert!(map.in_bounds(0, 1) && !map.passable(0, 1, true));
+        assert!(!map.passable(1, 1, false) && map.passable(1, 1, true));
+        assert!(map.passable(2, 1, false) && map.passable(1, 2, false));
+        assert_eq!(map.defence(2, 1), 1.);
+        assert_eq!(map.terrain(1, 2), Some(Terrain::Shrine));
+        assert_eq!(Vec::<String>::from(map)[1], "=~^");
+
+        assert!(parse(&["1..", "2."]).is_err());
+        assert!(parse(&["1.", "1."]).is_err());
+        assert!(parse(&["1?", "2."]).is_err());
+    }
+}
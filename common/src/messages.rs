@@ -1,11 +1,19 @@
-use crate::card::{Card, CardEntity};
+use crate::card::{Card, CardEntity, Trigger};
+use crate::codec::Codec;
+use crate::rules::RuleSet;
+use crate::status::StatusEffect;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum ServerMessage {
+    // 1st param: the codec the server will use from now on (sent as JSON)
+    Handshake(Codec),
     // 1st param: whether or not the player is player_1
-    StartGame(bool),
+    // 2nd param: the rules of the game
+    StartGame(bool, RuleSet),
     StartTurn,
+    // the turn timer ran out and the server ended the receiver's turn
+    TurnTimedOut,
     // 1st param: the `CardEntity` to spawn
     SpawnCard(CardEntity),
     /*
@@ -15,13 +23,85 @@ pub enum ServerMessage {
     4th param: the final   y position
     */
     MoveTroop(i32, i32, i32, i32),
-    AttackTroop(i32, i32, i32, i32),
+    AttackResult(AttackResult),
+    // the troop on (`x`, `y`) now has `hp`, e.g. after healing
+    HpChanged { x: i32, y: i32, hp: f32 },
+    // these troops as they are now, replacing the ones on the same tiles,
+    // e.g. after being reset for their owner's turn
+    TroopsChanged(Vec<CardEntity>),
+    // the receiver's pawns and spirits, whenever either changes
+    Currency { pawns: i32, spirits: i32 },
+    // what triggered abilities did, in order
+    AbilityEvents(Vec<AbilityEvent>),
     EndGame(bool),
     ChatMessage(String),
+    // the receiver's last chat message wasn't passed on, and why
+    ChatRejected(String),
+    // heartbeat, answered with a `Pong` carrying the same value
+    Ping(u64),
+    Pong(u64),
+    // a notice from the server's operators, shown to every player
+    Announcement(String),
+    // the server stops taking players and exits in `seconds`; games still
+    // running by then end without a winner
+    ServerShuttingDown { seconds: u64 },
+    // the server is about to close the connection, and why
+    Disconnect(String),
+}
+
+// One step of resolving triggered abilities. Clients apply them in order and
+// can show each one as it happens. Troops that changed are sent as they are
+// afterwards, on their own tiles.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AbilityEvent {
+    // the troop on (`x`, `y`), or the one that just died there, used a
+    // triggered ability
+    Triggered {
+        x: i32,
+        y: i32,
+        trigger: Trigger,
+    },
+    // `damage` is what got through shields and armor
+    Damaged {
+        damage: f32,
+        troop: CardEntity,
+    },
+    Died {
+        x: i32,
+        y: i32,
+    },
+    Summoned(CardEntity),
+    // see `CardEntity::buff`
+    Buffed {
+        attack: f32,
+        hp: f32,
+        troop: CardEntity,
+    },
+    // see `CardEntity::add_status`
+    StatusAdded {
+        effect: StatusEffect,
+        troop: CardEntity,
+    },
+}
+
+// How the attack of the troop on `from` on the one on `to` went. The damage
+// is what got through shields and armor, `retaliation` is `None` if the
+// target didn't strike back. Both troops are as they are now, on their new
+// tiles, or `None` if they died.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AttackResult {
+    pub from: (i32, i32),
+    pub to: (i32, i32),
+    pub damage: f32,
+    pub retaliation: Option<f32>,
+    pub attacker: Option<CardEntity>,
+    pub target: Option<CardEntity>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum ClientMessage {
+    // always the first packet, always JSON
+    Handshake(Codec),
     PlayerInfo(String, Vec<Card>),
     MoveTroop(i32, i32, i32, i32),
     AttackTroop(i32, i32, i32, i32),
@@ -30,4 +110,6 @@ pub enum ClientMessage {
     WinGame(i32, i32),
     ChatMessage(String),
     Resign,
+    Ping(u64),
+    Pong(u64),
 }
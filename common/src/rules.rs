@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::map::Map;
+
+// The numbers a game is played by. The server takes them from its config and
+// sends them to both players with `StartGame`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuleSet {
+    pub starting_pawns: i32,
+    pub starting_spirits: i32,
+    // spirits a player gets as each of their turns starts
+    pub spirit_income: i32,
+    // and on top of that for each shrine one of their troops stands on
+    pub shrine_income: i32,
+    // how many rows on their own side players can put troops on
+    pub spawn_rows: i32,
+    // the part of a killed troop's cost its killer gets in spirits, spirit
+    // collectors get all of it
+    pub kill_reward_percent: i32,
+    // every surviving defender strikes back, see `CardEntity::retaliates`
+    pub retaliation: bool,
+    // 0 for no limit
+    pub turn_timer_secs: u64,
+    pub map: Map,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            starting_pawns: 6,
+            starting_spirits: 8,
+            spirit_income: 1,
+            shrine_income: 1,
+            spawn_rows: 4,
+            kill_reward_percent: 50,
+            retaliation: false,
+            turn_timer_secs: 0,
+            map: Map::default(),
+        }
+    }
+}
+
+impl RuleSet {
+    pub fn turn_timer(&self) -> Option<Duration> {
+        match self.turn_timer_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    // Whether a player can put troops on a tile, in player 1's coordinates.
+    pub fn may_spawn_on(&self, (x, y): (i32, i32), is_player_1: bool) -> bool {
+        if !self.map.in_bounds(x, y) {
+            false
+        } else if is_player_1 {
+            y >= self.map.height() - self.spawn_rows
+        } else {
+            y < self.spawn_rows
+        }
+    }
+
+    pub fn kill_reward(&self, cost: i32, spirit_collector: bool) -> i32 {
+        if spirit_collector {
+            cost
+        } else {
+            cost * self.kill_reward_percent / 100
+        }
+    }
+
+    // Why the rules can't be played by, if they can't.
+    pub fn problem(&self) -> Option<String> {
+        if self.starting_pawns < 1 {
+            return Some("starting_pawns has to be at least 1".to_owned());
+        }
+        if self.starting_spirits < 0 || self.spirit_income < 0 || self.shrine_income < 0 {
+            return Some(
+                "starting_spirits, spirit_income and shrine_income can't be negative".to_owned(),
+            );
+        }
+        if self.spawn_rows < 1 || self.spawn_rows > self.map.height() / 2 {
+            return Some(format!(
+                "spawn_rows has to be between 1 and {}, half the map's height",
+                self.map.height() / 2
+            ));
+        }
+        if !(0..=100).contains(&self.kill_reward_percent) {
+            return Some("kill_reward_percent has to be between 0 and 100".to_owned());
+        }
+        if self.turn_timer_secs != 0 && self.turn_timer_secs < 5 {
+            return Some("turn_timer_secs has to be 0 (no limit) or at least 5".to_owned());
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn players_spawn_on_their_own_side() {
+        let rules = RuleSet::default();
+        assert!(rules.may_spawn_on((0, 8), true));
+        assert!(rules.may_spawn_on((2, 5), true));
+        assert!(!rules.may_spawn_on((2, 4), true));
+        assert!(!rules.may_spawn_on((2, 4), false));
+        assert!(rules.may_spawn_on((4, 3), false));
+        assert!(!rules.may_spawn_on((2, 8), false));
+        assert!(!rules.may_spawn_on((5, 8), true));
+
+        assert_eq!(rules.kill_reward(5, false), 2);
+        assert_eq!(rules.kill_reward(5, true), 5);
+        assert!(rules.problem().is_none());
+        let rules = RuleSet {
+            spawn_rows: 5,
+            ..RuleSet::default()
+        };
+        assert!(rules.problem().is_some());
+    }
+}
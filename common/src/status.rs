@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    // can't move, attack or win the game
+    Stun,
+    // takes damage every turn, poisoning a troop again adds to it
+    Poison,
+    // takes damage every turn, only the strongest burn counts
+    Burn,
+    // moves a single tile at most
+    Slow,
+    // can't move, but can still attack
+    Root,
+    // enemies that can reach a taunting troop have to attack it first
+    Taunt,
+    // its triggered, healing and on-hit abilities do nothing
+    Silence,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Stun => "Stun",
+            Status::Poison => "Poison",
+            Status::Burn => "Burn",
+            Status::Slow => "Slow",
+            Status::Root => "Root",
+            Status::Taunt => "Taunt",
+            Status::Silence => "Silence",
+        }
+    }
+}
+
+// A status on a troop. It lasts for the troop's next `turns` turns, or
+// until its owner's next turn starts when `turns` is 0.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StatusEffect {
+    pub status: Status,
+    pub turns: u8,
+    // taken at the start of each of those turns, ignoring shields and armor
+    pub damage: f32,
+}
+
+impl StatusEffect {
+    pub fn new(status: Status, turns: u8) -> StatusEffect {
+        StatusEffect {
+            status,
+            turns,
+            damage: 0.,
+        }
+    }
+
+    pub fn damaging(status: Status, damage: f32, turns: u8) -> StatusEffect {
+        StatusEffect {
+            status,
+            turns,
+            damage,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let turns = if self.turns == 1 { "turn" } else { "turns" };
+        if self.damage > 0. {
+            format!(
+                "{} {} ({} {})",
+                self.status.name(),
+                self.damage,
+                self.turns,
+                turns
+            )
+        } else {
+            format!("{} ({} {})", self.status.name(), self.turns, turns)
+        }
+    }
+}
+
+// Adds `effect` to a troop's statuses. A status the troop already has lasts
+// as long as the longer of the two, and its damage is added up for poison
+// or the highest of the two otherwise.
+pub fn apply(statuses: &mut Vec<StatusEffect>, effect: StatusEffect) {
+    match statuses.iter_mut().find(|old| old.status == effect.status) {
+        Some(old) => {
+            old.turns = old.turns.max(effect.turns);
+            if effect.status == Status::Poison {
+                old.damage += effect.damage;
+            } else {
+                old.damage = old.damage.max(effect.damage);
+            }
+        }
+        None => statuses.push(effect),
+    }
+}
+
+// Called as the troop's turn starts: drops the statuses that have run out
+// and counts down the rest. Returns the damage they deal this turn.
+pub fn tick(statuses: &mut Vec<StatusEffect>) -> f32 {
+    statuses.retain(|effect| effect.turns > 0);
+    let mut damage = 0.;
+    for effect in statuses.iter_mut() {
+        effect.turns -= 1;
+        damage += effect.damage;
+    }
+    damage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_stack_and_run_out() {
+        let mut statuses = Vec::new();
+        apply(&mut statuses, StatusEffect::damaging(Status::Poison, 1., 2));
+        apply(&mut statuses, StatusEffect::damaging(Status::Poison, 1., 1));
+        apply(&mut statuses, StatusEffect::damaging(Status::Burn, 2., 1));
+        apply(&mut statuses, StatusEffect::damaging(Status::Burn, 1., 3));
+        apply(&mut statuses, StatusEffect::new(Status::Root, 0));
+        assert_eq!(
+            statuses,
+            [
+                StatusEffect::damaging(Status::Poison, 2., 2),
+                StatusEffect::damaging(Status::Burn, 2., 3),
+                StatusEffect::new(Status::Root, 0),
+            ]
+        );
+
+        // the root only lasted until this turn
+        assert_eq!(tick(&mut statuses), 4.);
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(tick(&mut statuses), 4.);
+        assert_eq!(tick(&mut statuses), 2.);
+        assert_eq!(tick(&mut statuses), 0.);
+        assert!(statuses.is_empty());
+    }
+}
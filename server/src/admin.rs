@@ -0,0 +1,361 @@
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::info;
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+
+use crate::bans::{self, Ban, Target};
+use crate::state::ServerState;
+
+const HELP: &str = "\
+commands:
+  list                      connected clients
+  games                     running games
+  kick <username|ip|range> [why]
+                            disconnect matching players
+  ban <username|ip|range> [for] [why]
+                            disconnect matching players and keep them out,
+                            for a time like 30m, 12h or 7d, or for good
+  unban <username|ip|range>
+  bans                      banned usernames and addresses
+  mute <username|ip|range> [for]
+                            stop matching players from chatting, for a time
+                            like 10m or until unmuted
+  unmute <username|ip|range>
+  announce <text>           message every connected player
+  end <game id>             end a game without a winner
+  shutdown [seconds]        stop taking players, exit once the games are over
+                            or after `shutdown_grace_secs`, whichever is first
+  help";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Help,
+    List,
+    Games,
+    Kick(Target, Option<String>),
+    // seconds the ban lasts, for good if missing
+    Ban(Target, Option<u64>, Option<String>),
+    Unban(Target),
+    Bans,
+    // seconds the mute lasts, until unmuted if missing
+    Mute(Target, Option<u64>),
+    Unmute(Target),
+    Announce(String),
+    End(u64),
+    // seconds the running games get, the configured grace period if missing
+    Shutdown(Option<u64>),
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Command, String> {
+        let line = line.trim();
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim()),
+            None => (line, ""),
+        };
+        let target = || match rest.split_whitespace().next() {
+            Some(target) => Ok(Target::parse(target)),
+            None => Err(format!("usage: {} <username|ip|range>", name)),
+        };
+        match name {
+            "help" | "?" => Ok(Command::Help),
+            "list" => Ok(Command::List),
+            "games" => Ok(Command::Games),
+            "kick" => {
+                let reason = rest
+                    .split_once(char::is_whitespace)
+                    .map(|(_, reason)| reason.trim().to_owned());
+                Ok(Command::Kick(target()?, reason))
+            }
+            "ban" => {
+                let target = target()?;
+                let mut rest = rest.split_whitespace().skip(1).peekable();
+                let length = rest.peek().and_then(|length| bans::parse_duration(length));
+                if length.is_some() {
+                    rest.next();
+                }
+                let reason: Vec<&str> = rest.collect();
+                let reason = (!reason.is_empty()).then(|| reason.join(" "));
+                Ok(Command::Ban(target, length, reason))
+            }
+            "unban" => Ok(Command::Unban(target()?)),
+            "bans" => Ok(Command::Bans),
+            "mute" => {
+                let target = target()?;
+                match rest.split_whitespace().nth(1) {
+                    Some(length) => match bans::parse_duration(length) {
+                        Some(length) => Ok(Command::Mute(target, Some(length))),
+                        None => Err("usage: mute <username|ip|range> [10m|2h|1d]".to_owned()),
+                    },
+                    None => Ok(Command::Mute(target, None)),
+                }
+            }
+            "unmute" => Ok(Command::Unmute(target()?)),
+            "announce" if !rest.is_empty() => Ok(Command::Announce(rest.to_owned())),
+            "announce" => Err("usage: announce <text>".to_owned()),
+            "end" => rest
+                .parse()
+                .map(Command::End)
+                .map_err(|_| "usage: end <game id>".to_owned()),
+            "shutdown" if rest.is_empty() => Ok(Command::Shutdown(None)),
+            "shutdown" => rest
+                .parse()
+                .map(|seconds| Command::Shutdown(Some(seconds)))
+                .map_err(|_| "usage: shutdown [seconds]".to_owned()),
+            _ => Err(format!("unknown command {:?}, try `help`", name)),
+        }
+    }
+}
+
+fn execute(state: &ServerState, command: Command, shutdown_grace: Duration) -> String {
+    let mut out = String::new();
+    match command {
+        Command::Help => out.push_str(HELP),
+        Command::List => {
+            let connections = state.connections();
+            let _ = write!(out, "{} connected", connections.len());
+            for connection in connections {
+                let _ = write!(
+                    out,
+                    "\n  #{} {} {}",
+                    connection.handle.id(),
+                    connection.handle.addr(),
+                    connection.username.as_deref().unwrap_or("-"),
+                );
+                if let Some(game) = connection.game {
+                    let _ = write!(out, " in game {}", game);
+                }
+            }
+        }
+        Command::Games => {
+            let games = state.games();
+            let _ = write!(out, "{} running", games.len());
+            for (id, game) in games {
+                let [p1, p2] = game
+                    .players
+                    .map(|player| state.username(player).unwrap_or_else(|| "-".to_owned()));
+                let _ = write!(
+                    out,
+                    "\n  {}: {} vs {}, {}s",
+                    id,
+                    p1,
+                    p2,
+                    game.started.elapsed().as_secs()
+                );
+            }
+        }
+        Command::Kick(target, reason) => {
+            let reason = reason.unwrap_or_else(|| "You have been kicked".to_owned());
+            let kicked = state.kick(&target, &reason);
+            let _ = write!(out, "kicked {} client(s) matching {}", kicked, target);
+        }
+        Command::Ban(target, length, reason) => {
+            let created = bans::unix_now();
+            let ban = Ban {
+                target,
+                reason: reason.unwrap_or_default(),
+                created,
+                expires: length.map(|length| created + length),
+            };
+            let _ = write!(out, "banned {} ({})", ban.target, ban.remaining());
+            let (kicked, saved) = state.ban(ban);
+            let _ = write!(out, ", kicked {} client(s)", kicked);
+            if let Err(e) = saved {
+                let _ = write!(
+                    out,
+                    "\ncouldn't save the ban list, it won't last a restart: {}",
+                    e
+                );
+            }
+        }
+        Command::Unban(target) => match state.unban(&target) {
+            Ok(true) => {
+                let _ = write!(out, "unbanned {}", target);
+            }
+            Ok(false) => {
+                let _ = write!(out, "{} wasn't banned", target);
+            }
+            Err(e) => {
+                let _ = write!(
+                    out,
+                    "unbanned {}, but couldn't save the ban list: {}",
+                    target, e
+                );
+            }
+        },
+        Command::Bans => {
+            let bans = state.bans();
+            let _ = write!(out, "{} banned", bans.len());
+            for ban in bans {
+                let _ = write!(out, "\n  {} ({})", ban.target, ban.remaining());
+                if !ban.reason.is_empty() {
+                    let _ = write!(out, " {}", ban.reason);
+                }
+            }
+        }
+        Command::Mute(target, length) => {
+            state.mute(target.clone(), length.map(Duration::from_secs));
+            let _ = write!(
+                out,
+                "muted {} ({})",
+                target,
+                length.map_or_else(|| "until unmuted".to_owned(), bans::format_duration)
+            );
+        }
+        Command::Unmute(target) => {
+            if state.unmute(&target) {
+                let _ = write!(out, "unmuted {}", target);
+            } else {
+                let _ = write!(out, "{} wasn't muted", target);
+            }
+        }
+        Command::Announce(text) => {
+            let reached = state.announce(&text);
+            let _ = write!(out, "announced to {} client(s)", reached);
+        }
+        Command::End(id) => {
+            if state.end_game(id) {
+                let _ = write!(out, "ending game {}", id);
+            } else {
+                let _ = write!(out, "no game {}", id);
+            }
+        }
+        Command::Shutdown(seconds) => {
+            let grace = seconds.map_or(shutdown_grace, Duration::from_secs);
+            state.start_draining(grace);
+            let _ = write!(
+                out,
+                "draining, exiting once the {} running game(s) are over or in {}s",
+                state.games().len(),
+                grace.as_secs()
+            );
+        }
+    }
+    out
+}
+
+// Reads commands from stdin until it closes. Without a terminal, e.g. when
+// stdin is /dev/null, that's right away and the server runs on as before.
+pub async fn console(state: Arc<ServerState>, shutdown_grace: Duration) {
+    let mut lines = BufReader::new(io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match Command::parse(&line) {
+            Ok(command) => {
+                info!("admin: {}", line.trim());
+                println!("{}", execute(&state, command, shutdown_grace));
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::net::client::Client;
+    use common::{codec::Codec, messages::ServerMessage};
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn commands_parse() {
+        assert_eq!(
+            Command::parse("  kick 10.0.0.1 stop spamming "),
+            Ok(Command::Kick(
+                Target::Network("10.0.0.1".parse().unwrap()),
+                Some("stop spamming".to_owned())
+            ))
+        );
+        assert_eq!(
+            Command::parse("ban Griefer"),
+            Ok(Command::Ban(
+                Target::Username("Griefer".to_owned()),
+                None,
+                None
+            ))
+        );
+        assert_eq!(
+            Command::parse("ban 10.1.0.0/16 2h open proxies"),
+            Ok(Command::Ban(
+                Target::Network("10.1.0.0/16".parse().unwrap()),
+                Some(7200),
+                Some("open proxies".to_owned())
+            ))
+        );
+        assert_eq!(
+            Command::parse("ban griefer spamming chat"),
+            Ok(Command::Ban(
+                Target::Username("griefer".to_owned()),
+                None,
+                Some("spamming chat".to_owned())
+            ))
+        );
+        assert_eq!(
+            Command::parse("announce back in 5 minutes"),
+            Ok(Command::Announce("back in 5 minutes".to_owned()))
+        );
+        assert_eq!(
+            Command::parse("mute griefer 10m"),
+            Ok(Command::Mute(
+                Target::Username("griefer".to_owned()),
+                Some(600)
+            ))
+        );
+        assert!(Command::parse("mute griefer soon").is_err());
+        assert_eq!(Command::parse("end 3"), Ok(Command::End(3)));
+        assert_eq!(
+            Command::parse("shutdown 30"),
+            Ok(Command::Shutdown(Some(30)))
+        );
+        assert!(Command::parse("end three").is_err());
+        assert!(Command::parse("kick").is_err());
+        assert!(Command::parse("announce").is_err());
+        assert!(Command::parse("reboot").is_err());
+    }
+
+    #[tokio::test]
+    async fn banned_players_are_kicked_and_stay_out() {
+        let state = ServerState::default();
+        let (server_end, mut client_end) = tokio::io::duplex(1024);
+        let addr = "10.0.0.1:5000".parse().unwrap();
+        let client = Client::new(server_end, addr, &Config::default());
+        state.add_connection(client.handle());
+        state.set_username(client.id(), "Griefer");
+
+        execute(
+            &state,
+            Command::parse("ban griefer 1d spam").unwrap(),
+            Duration::ZERO,
+        );
+        assert_eq!(state.player_ban(addr, "GRIEFER").unwrap().reason, "spam");
+        assert!(state.address_ban(addr).is_none());
+        let len = client_end.read_u32().await.unwrap();
+        let mut payload = vec![0; len as usize];
+        client_end.read_exact(&mut payload).await.unwrap();
+        assert_eq!(
+            Codec::Json.decode::<ServerMessage>(&payload).unwrap(),
+            ServerMessage::Announcement("You are banned from this server for 1d: spam".to_owned())
+        );
+        // the writer stops once it has sent the announcement
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while client.is_connected() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+
+        execute(
+            &state,
+            Command::parse("unban Griefer").unwrap(),
+            Duration::ZERO,
+        );
+        assert!(state.player_ban(addr, "griefer").is_none());
+        assert_eq!(state.announce("hello"), 0);
+    }
+}
@@ -0,0 +1,396 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+// An address range like `10.0.0.0/8`. A single address is a range with the
+// full prefix length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn max_prefix(addr: &IpAddr) -> u8 {
+        match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // keep the address family from mixing, except for v4-mapped v6
+        let ip = match ip {
+            IpAddr::V6(v6) if self.network.is_ipv4() => match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => return false,
+            },
+            ip => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(ip: IpAddr) -> Self {
+        Cidr {
+            network: ip,
+            prefix: Cidr::max_prefix(&ip),
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = network
+            .parse()
+            .map_err(|_| format!("{:?} isn't an IP address", network))?;
+        let max_prefix = Cidr::max_prefix(&network);
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse() {
+                Ok(prefix) if prefix <= max_prefix => prefix,
+                _ => return Err(format!("{:?} isn't a valid prefix length", prefix)),
+            },
+            None => max_prefix,
+        };
+        Ok(Cidr { network, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix == Cidr::max_prefix(&self.network) {
+            write!(f, "{}", self.network)
+        } else {
+            write!(f, "{}/{}", self.network, self.prefix)
+        }
+    }
+}
+
+// Someone to kick or ban, by address range or by the username they play
+// under. Usernames match case-insensitively.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    Network(Cidr),
+    Username(String),
+}
+
+impl Target {
+    pub fn parse(target: &str) -> Target {
+        match target.parse() {
+            Ok(network) => Target::Network(network),
+            Err(_) => Target::Username(target.to_owned()),
+        }
+    }
+
+    pub fn matches(&self, ip: IpAddr, username: Option<&str>) -> bool {
+        match (self, username) {
+            (Target::Network(network), _) => network.contains(ip),
+            (Target::Username(name), Some(username)) => name.eq_ignore_ascii_case(username),
+            (Target::Username(_), None) => false,
+        }
+    }
+
+    // the same ban or mute, usernames compared case-insensitively
+    pub fn same_as(&self, other: &Target) -> bool {
+        match (self, other) {
+            (Target::Username(a), Target::Username(b)) => a.eq_ignore_ascii_case(b),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Network(network) => write!(f, "{}", network),
+            Self::Username(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    pub target: Target,
+    pub reason: String,
+    // unix seconds
+    pub created: u64,
+    // `None` for a permanent ban
+    pub expires: Option<u64>,
+}
+
+impl Ban {
+    pub fn is_active(&self, now: u64) -> bool {
+        match self.expires {
+            Some(expires) => now < expires,
+            None => true,
+        }
+    }
+
+    // "permanent" or how long is left, e.g. "2d 3h"
+    pub fn remaining(&self) -> String {
+        match self.expires {
+            Some(expires) => format_duration(expires.saturating_sub(unix_now())),
+            None => "permanent".to_owned(),
+        }
+    }
+
+    // what the banned player is told
+    pub fn notice(&self) -> String {
+        let mut notice = match self.expires {
+            Some(_) => format!("You are banned from this server for {}", self.remaining()),
+            None => "You are banned from this server".to_owned(),
+        };
+        if !self.reason.is_empty() {
+            notice.push_str(": ");
+            notice.push_str(&self.reason);
+        }
+        notice
+    }
+}
+
+// The two largest units, e.g. "1d 4h" or "5m 30s".
+pub fn format_duration(seconds: u64) -> String {
+    let units = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(seconds, |left, (unit, size)| {
+            let count = *left / size;
+            *left %= size;
+            Some((count, unit))
+        })
+        .skip_while(|(count, _)| *count == 0)
+        .take(2)
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, unit)| format!("{}{}", count, unit))
+        .collect();
+    if parts.is_empty() {
+        "0s".to_owned()
+    } else {
+        parts.join(" ")
+    }
+}
+
+// Parses "30s", "15m", "2h", "7d" or "1w".
+pub fn parse_duration(text: &str) -> Option<u64> {
+    let split = text.len().checked_sub(1)?;
+    if !text.is_char_boundary(split) {
+        return None;
+    }
+    let (count, unit) = text.split_at(split);
+    let count: u64 = count.parse().ok()?;
+    let size = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return None,
+    };
+    count.checked_mul(size)
+}
+
+// Bans kept in a JSON file, rewritten on every change. Expired bans are
+// ignored and dropped the next time the file is written.
+#[derive(Default)]
+pub struct BanList {
+    // `None` keeps the list in memory only
+    path: Option<PathBuf>,
+    bans: Vec<Ban>,
+}
+
+impl BanList {
+    // A missing file is an empty list.
+    pub fn load(path: &Path) -> io::Result<BanList> {
+        let bans = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(BanList {
+            path: Some(path.to_owned()),
+            bans,
+        })
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        let now = unix_now();
+        self.bans.retain(|ban| ban.is_active(now));
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = serde_json::to_string_pretty(&self.bans).expect("bans always serialize");
+        // written next to it first, so a crash can't leave half a file
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, text)?;
+        fs::rename(&temporary, path)
+    }
+
+    // Replaces any ban on the same target. The ban applies either way, an
+    // error only means it won't survive a restart.
+    pub fn add(&mut self, ban: Ban) -> io::Result<()> {
+        self.bans
+            .retain(|existing| !existing.target.same_as(&ban.target));
+        self.bans.push(ban);
+        self.save()
+    }
+
+    // `Ok(false)` if `target` wasn't banned
+    pub fn remove(&mut self, target: &Target) -> io::Result<bool> {
+        let count = self.bans.len();
+        self.bans.retain(|ban| !ban.target.same_as(target));
+        if self.bans.len() == count {
+            return Ok(false);
+        }
+        self.save().map(|()| true)
+    }
+
+    pub fn active(&self) -> Vec<Ban> {
+        let now = unix_now();
+        self.bans
+            .iter()
+            .filter(|ban| ban.is_active(now))
+            .cloned()
+            .collect()
+    }
+
+    // the first active ban that covers `ip`, or `username` if it's known
+    pub fn find(&self, ip: IpAddr, username: Option<&str>) -> Option<Ban> {
+        let now = unix_now();
+        self.bans
+            .iter()
+            .find(|ban| ban.is_active(now) && ban.target.matches(ip, username))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_ranges() {
+        let range: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(range.contains(ip("10.1.200.3")));
+        assert!(!range.contains(ip("10.2.0.1")));
+        assert!(range.contains(ip("::ffff:10.1.0.9")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+        let range: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(range.contains(ip("2001:db8:1::1")));
+        assert!(!range.contains(ip("10.1.0.1")));
+        assert_eq!(Cidr::from(ip("10.0.0.1")).to_string(), "10.0.0.1");
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("griefer".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90m"), Some(5400));
+        assert_eq!(parse_duration("7d"), Some(604800));
+        assert_eq!(parse_duration("d"), None);
+        assert_eq!(parse_duration("spam"), None);
+        assert_eq!(format_duration(5400), "1h 30m");
+        assert_eq!(format_duration(86400 + 59), "1d");
+        assert_eq!(format_duration(0), "0s");
+    }
+
+    #[test]
+    fn bans_persist_and_expire() {
+        let path = std::env::temp_dir()
+            .join(format!("8bit-duels-bans-{}", std::process::id()))
+            .join("bans.json");
+        let _ = fs::remove_file(&path);
+        let now = unix_now();
+        let mut bans = BanList::load(&path).unwrap();
+        bans.add(Ban {
+            target: Target::parse("10.0.0.0/8"),
+            reason: "botnet".to_owned(),
+            created: now,
+            expires: None,
+        })
+        .unwrap();
+        bans.add(Ban {
+            target: Target::parse("Griefer"),
+            reason: "spam".to_owned(),
+            created: now,
+            expires: Some(now + 3600),
+        })
+        .unwrap();
+        bans.add(Ban {
+            target: Target::parse("old"),
+            reason: "long ago".to_owned(),
+            created: now - 7200,
+            expires: Some(now - 3600),
+        })
+        .unwrap();
+
+        let bans = BanList::load(&path).unwrap();
+        assert_eq!(bans.active().len(), 2);
+        assert_eq!(bans.find(ip("10.20.30.40"), None).unwrap().reason, "botnet");
+        assert_eq!(
+            bans.find(ip("127.0.0.1"), Some("GRIEFER")).unwrap().reason,
+            "spam"
+        );
+        assert!(bans.find(ip("127.0.0.1"), Some("old")).is_none());
+        assert!(bans.find(ip("127.0.0.1"), None).is_none());
+
+        let mut bans = bans;
+        assert!(bans.remove(&Target::parse("griefer")).unwrap());
+        assert!(!bans.remove(&Target::parse("griefer")).unwrap());
+        assert_eq!(BanList::load(&path).unwrap().active().len(), 1);
+    }
+}
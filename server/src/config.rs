@@ -0,0 +1,517 @@
+use std::{
+    error::Error,
+    ffi::OsStr,
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::Parser;
+use common::rules::RuleSet;
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+
+#[derive(Parser, Debug)]
+#[command(about = "8bit Duels game server")]
+pub struct Cli {
+    /// Config file to load, TOML unless the extension is `.ron`
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, overrides the config file
+    #[arg(long)]
+    pub bind: Option<IpAddr>,
+    /// Port to listen on, overrides the config file
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    /// Only accept local connections
+    #[arg(long)]
+    pub dev: bool,
+    /// Overrides the config file
+    #[arg(long)]
+    pub log_level: Option<LevelFilter>,
+    /// Print the effective config as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub max_games: usize,
+    // 0 leaves it to the rules
+    pub turn_timer_secs: u64,
+    // how often clients are pinged, and how long one may stay silent
+    pub heartbeat_interval_secs: u64,
+    pub idle_timeout_secs: u64,
+    // how long running games get to finish once the server is told to stop
+    pub shutdown_grace_secs: u64,
+    pub log_level: LevelFilter,
+    // where the server keeps anything it has to remember across restarts
+    pub storage_path: PathBuf,
+    pub chat: ChatConfig,
+    pub rate_limits: RateLimitConfig,
+    pub rules: RulePreset,
+    // played by instead of the preset when set, anything left out is as in
+    // the standard rules
+    pub custom_rules: Option<RuleSet>,
+    pub metrics: MetricsConfig,
+    pub match_log: MatchLogConfig,
+    // plain TCP when missing
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: Ipv4Addr::UNSPECIFIED.into(),
+            port: 7777,
+            max_games: 64,
+            turn_timer_secs: 0,
+            heartbeat_interval_secs: 5,
+            idle_timeout_secs: 20,
+            shutdown_grace_secs: 120,
+            log_level: LevelFilter::Info,
+            storage_path: PathBuf::from("data"),
+            chat: ChatConfig::default(),
+            rate_limits: RateLimitConfig::default(),
+            rules: RulePreset::default(),
+            custom_rules: None,
+            metrics: MetricsConfig::default(),
+            match_log: MatchLogConfig::default(),
+            tls: None,
+        }
+    }
+}
+
+// What happens to chat messages with profanity in them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfanityPolicy {
+    // sent with the offending words starred out
+    #[default]
+    Censor,
+    // not sent, the sender is told why
+    Reject,
+    Allow,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    // in characters
+    pub max_length: usize,
+    pub profanity: ProfanityPolicy,
+    // how long a player has to wait between messages, 0 for no limit
+    pub slow_mode_secs: u64,
+    // writes every match's chat, as sent, to `chat/` in `storage_path`
+    pub log: bool,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        ChatConfig {
+            max_length: 20,
+            profanity: ProfanityPolicy::default(),
+            slow_mode_secs: 0,
+            log: true,
+        }
+    }
+}
+
+// A token bucket: `burst` packets at once, refilled at `per_second`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Bucket {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl Bucket {
+    fn validate(&self, name: &str) -> Result<(), ConfigError> {
+        if !(self.per_second.is_finite() && self.per_second > 0.) || self.burst == 0 {
+            return Err(ConfigError::Invalid(format!(
+                "rate_limits.{} needs a positive per_second and a burst of at least 1",
+                name
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Limits on what a single connection may send. Packets over a limit are
+// dropped and cost a strike; a peer that runs out of strikes is disconnected.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    // every packet, heartbeats included
+    pub connection: Bucket,
+    pub chat: Bucket,
+    // moves, attacks, spawns and the other game commands
+    pub actions: Bucket,
+    pub strikes: Bucket,
+    // decoded packets waiting for the game; a peer that fills this up is
+    // disconnected
+    pub max_queued_packets: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            connection: Bucket {
+                per_second: 20.,
+                burst: 40,
+            },
+            chat: Bucket {
+                per_second: 1.,
+                burst: 5,
+            },
+            actions: Bucket {
+                per_second: 10.,
+                burst: 20,
+            },
+            strikes: Bucket {
+                per_second: 0.2,
+                burst: 10,
+            },
+            max_queued_packets: 64,
+        }
+    }
+}
+
+// The HTTP endpoint with `/metrics` and `/health`. It has no authentication,
+// so it listens on localhost unless told otherwise.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub bind_address: IpAddr,
+    pub port: u16,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: true,
+            bind_address: Ipv4Addr::LOCALHOST.into(),
+            port: 9777,
+        }
+    }
+}
+
+impl MetricsConfig {
+    pub fn listen_address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+}
+
+// JSON lines describing every match. `path` is relative to `storage_path`,
+// `-` writes to stdout instead.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchLogConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+}
+
+impl Default for MatchLogConfig {
+    fn default() -> Self {
+        MatchLogConfig {
+            enabled: true,
+            path: PathBuf::from("matches.jsonl"),
+        }
+    }
+}
+
+// PEM files for the listener's certificate chain and its private key
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub certificate: PathBuf,
+    pub private_key: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RulePreset {
+    #[default]
+    Standard,
+    // standard rules with a short turn timer
+    Blitz,
+    // more spirits to start with and to earn, so bigger troops come out early
+    HighEconomy,
+}
+
+impl RulePreset {
+    fn rule_set(&self) -> RuleSet {
+        match self {
+            Self::Standard => RuleSet::default(),
+            Self::Blitz => RuleSet {
+                turn_timer_secs: 30,
+                ..RuleSet::default()
+            },
+            Self::HighEconomy => RuleSet {
+                starting_spirits: 16,
+                spirit_income: 3,
+                kill_reward_percent: 100,
+                ..RuleSet::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, Box<dyn Error + Send + Sync>),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "couldn't read {}: {}", path.display(), e),
+            Self::Parse(path, e) => write!(f, "couldn't parse {}: {}", path.display(), e),
+            Self::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(_, e) => Some(e),
+            Self::Parse(_, e) => Some(e.as_ref()),
+            Self::Invalid(_) => None,
+        }
+    }
+}
+
+impl Config {
+    // The config file, if any, with the command line applied on top.
+    pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None => Config::default(),
+        };
+        if cli.dev {
+            config.bind_address = Ipv4Addr::LOCALHOST.into();
+        }
+        if let Some(bind) = cli.bind {
+            config.bind_address = bind;
+        }
+        if let Some(port) = cli.port {
+            config.port = port;
+        }
+        if let Some(log_level) = cli.log_level {
+            config.log_level = log_level;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        if path.extension() == Some(OsStr::new("ron")) {
+            ron::from_str(&text).map_err(|e| ConfigError::Parse(path.to_owned(), e.into()))
+        } else {
+            toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_owned(), e.into()))
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.port == 0 {
+            return Err(ConfigError::Invalid("port can't be 0".to_owned()));
+        }
+        if self.max_games == 0 {
+            return Err(ConfigError::Invalid(
+                "max_games has to allow at least one game".to_owned(),
+            ));
+        }
+        if self.turn_timer_secs != 0 && self.turn_timer_secs < 5 {
+            return Err(ConfigError::Invalid(
+                "turn_timer_secs has to be 0 (preset default) or at least 5".to_owned(),
+            ));
+        }
+        if let Some(problem) = self.rule_set().problem() {
+            return Err(ConfigError::Invalid(format!("rules: {}", problem)));
+        }
+        if self.heartbeat_interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "heartbeat_interval_secs has to be at least 1".to_owned(),
+            ));
+        }
+        if self.idle_timeout_secs <= self.heartbeat_interval_secs {
+            return Err(ConfigError::Invalid(
+                "idle_timeout_secs has to be longer than heartbeat_interval_secs".to_owned(),
+            ));
+        }
+        if self.chat.max_length == 0 {
+            return Err(ConfigError::Invalid(
+                "chat.max_length has to be at least 1".to_owned(),
+            ));
+        }
+        if self.metrics.enabled && self.metrics.listen_address() == self.listen_address() {
+            return Err(ConfigError::Invalid(
+                "metrics.port can't be the game port".to_owned(),
+            ));
+        }
+        let limits = &self.rate_limits;
+        limits.connection.validate("connection")?;
+        limits.chat.validate("chat")?;
+        limits.actions.validate("actions")?;
+        limits.strikes.validate("strikes")?;
+        if limits.max_queued_packets == 0 {
+            return Err(ConfigError::Invalid(
+                "rate_limits.max_queued_packets has to be at least 1".to_owned(),
+            ));
+        }
+        if self.storage_path.is_file() {
+            return Err(ConfigError::Invalid(format!(
+                "storage_path {} is a file, not a directory",
+                self.storage_path.display()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config is always representable as TOML")
+    }
+
+    pub fn listen_address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval_secs)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+
+    // `None` when the match log is disabled
+    pub fn match_log_path(&self) -> Option<PathBuf> {
+        match &self.match_log {
+            MatchLogConfig { enabled: false, .. } => None,
+            MatchLogConfig { path, .. } if path == Path::new("-") => Some(path.clone()),
+            MatchLogConfig { path, .. } => Some(self.storage_path.join(path)),
+        }
+    }
+
+    // `None` when chat logging is disabled
+    pub fn chat_log_dir(&self) -> Option<PathBuf> {
+        self.chat.log.then(|| self.storage_path.join("chat"))
+    }
+
+    // kept up to date by the admin console's `ban` and `unban`
+    pub fn bans_path(&self) -> PathBuf {
+        self.storage_path.join("bans.json")
+    }
+
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_secs)
+    }
+
+    // the rules games are played by, with `turn_timer_secs` applied
+    pub fn rule_set(&self) -> RuleSet {
+        let mut rule_set = match &self.custom_rules {
+            Some(rule_set) => rule_set.clone(),
+            None => self.rules.rule_set(),
+        };
+        if self.turn_timer_secs != 0 {
+            rule_set.turn_timer_secs = self.turn_timer_secs;
+        }
+        rule_set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printed_config_loads_back() {
+        let config = Config {
+            port: 4000,
+            rules: RulePreset::Blitz,
+            custom_rules: Some(RuleSet {
+                retaliation: true,
+                ..RuleSet::default()
+            }),
+            log_level: LevelFilter::Debug,
+            ..Config::default()
+        };
+        let parsed: Config = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(parsed, config);
+    }
+
+    #[test]
+    fn partial_files_use_defaults() {
+        let config: Config =
+            toml::from_str("port = 4000\n[chat]\nmax_length = 50\nprofanity = \"reject\"\n")
+                .unwrap();
+        assert_eq!(config.port, 4000);
+        assert_eq!(config.chat.max_length, 50);
+        assert_eq!(config.chat.profanity, ProfanityPolicy::Reject);
+        assert!(config.chat.log);
+        assert_eq!(config.max_games, Config::default().max_games);
+
+        let config: Config = ron::from_str("(rules: blitz)").unwrap();
+        assert_eq!(
+            config.rule_set().turn_timer(),
+            Some(Duration::from_secs(30))
+        );
+        let config: Config = ron::from_str("(turn_timer_secs: 60, rules: blitz)").unwrap();
+        assert_eq!(
+            config.rule_set().turn_timer(),
+            Some(Duration::from_secs(60))
+        );
+
+        let config: Config = toml::from_str("[custom_rules]\nstarting_spirits = 12\n").unwrap();
+        assert_eq!(config.rule_set().starting_spirits, 12);
+        assert_eq!(config.rule_set().starting_pawns, 6);
+        let config: Config = toml::from_str("rules = \"high_economy\"").unwrap();
+        assert_eq!(config.rule_set().spirit_income, 3);
+
+        let config: Config = toml::from_str(
+            "[custom_rules]\nspawn_rows = 2\nmap = [\"1#1\", \"...\", \"...\", \"...\", \"2#2\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.rule_set().map.height(), 5);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn typos_and_bad_values_are_rejected() {
+        assert!(toml::from_str::<Config>("prot = 4000").is_err());
+        let config: Config = toml::from_str("max_games = 0").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        let config: Config = toml::from_str("turn_timer_secs = 2").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        assert!(toml::from_str::<Config>("[custom_rules]\nstarting_spirit = 12").is_err());
+        let config: Config = toml::from_str("[custom_rules]\nspawn_rows = 0").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        assert!(toml::from_str::<Config>("[custom_rules]\nmap = [\"1.\", \"2\"]").is_err());
+        let config: Config =
+            toml::from_str("[custom_rules]\nmap = [\"1\", \".\", \".\", \"2\"]").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        let config: Config =
+            toml::from_str("heartbeat_interval_secs = 10\nidle_timeout_secs = 10").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        let config: Config =
+            toml::from_str("[rate_limits.chat]\nper_second = 0\nburst = 5").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn command_line_overrides_the_file() {
+        let cli = Cli::parse_from(["server", "--dev", "--port", "4000", "--log-level", "warn"]);
+        let config = Config::load(&cli).unwrap();
+        assert_eq!(config.listen_address(), "127.0.0.1:4000".parse().unwrap());
+        assert_eq!(config.log_level, LevelFilter::Warn);
+    }
+}
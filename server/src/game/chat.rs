@@ -0,0 +1,218 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+
+use log::warn;
+use rustrict::CensorStr;
+use tokio::time::{Duration, Instant};
+
+use crate::bans::unix_now;
+use crate::config::{ChatConfig, ProfanityPolicy};
+
+// Why a message wasn't passed on, as its sender is told.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Refusal {
+    Muted,
+    Empty,
+    TooLong { max_length: usize },
+    // seconds until the player may chat again
+    SlowMode { wait: u64 },
+    Profanity,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::Muted => write!(f, "You are muted"),
+            Refusal::Empty => write!(f, "Nothing to send"),
+            Refusal::TooLong { max_length } => {
+                write!(f, "Messages can be at most {} characters", max_length)
+            }
+            Refusal::SlowMode { wait } => write!(f, "Slow mode is on, wait {}s", wait),
+            Refusal::Profanity => write!(f, "Watch your language"),
+        }
+    }
+}
+
+// One match's chat: every message goes through `moderate`, which applies
+// the server's chat rules and writes it to the chat log.
+pub struct Chat {
+    config: ChatConfig,
+    started: Instant,
+    // when each player last got a message through
+    last_sent: [Option<Instant>; 2],
+    // `None` when chat logging is disabled
+    log: Option<LineWriter<File>>,
+}
+
+impl Chat {
+    pub fn new(config: ChatConfig) -> Chat {
+        Chat {
+            config,
+            started: Instant::now(),
+            last_sent: [None; 2],
+            log: None,
+        }
+    }
+
+    // Logs to `<game>-<unix time>.log` in `dir`, game ids start over with
+    // every run of the server.
+    pub fn with_log(mut self, dir: &Path, game: u64) -> io::Result<Chat> {
+        fs::create_dir_all(dir)?;
+        let started = unix_now();
+        let path = dir.join(format!("{}-{}.log", game, started));
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut log = LineWriter::new(file);
+        writeln!(log, "game {} started at {} (unix time)", game, started)?;
+        self.log = Some(log);
+        Ok(self)
+    }
+
+    // Returns what the other player gets to see, or why they don't. `player`
+    // is 1 or 2, as in `Game`.
+    pub fn moderate(
+        &mut self,
+        player: u8,
+        username: &str,
+        muted: bool,
+        message: &str,
+        now: Instant,
+    ) -> Result<String, Refusal> {
+        let result = self.check(player, muted, message, now);
+        let outcome = match &result {
+            Ok(sent) if sent != message => " (censored)".to_owned(),
+            Ok(_) => String::new(),
+            Err(refusal) => format!(" (refused: {})", refusal),
+        };
+        let elapsed = now.saturating_duration_since(self.started).as_secs();
+        self.write_log(format_args!(
+            "[{:02}:{:02}] {}{}: {}",
+            elapsed / 60,
+            elapsed % 60,
+            username,
+            outcome,
+            message
+        ));
+        result
+    }
+
+    fn check(
+        &mut self,
+        player: u8,
+        muted: bool,
+        message: &str,
+        now: Instant,
+    ) -> Result<String, Refusal> {
+        if muted {
+            return Err(Refusal::Muted);
+        }
+        if message.trim().is_empty() {
+            return Err(Refusal::Empty);
+        }
+        let max_length = self.config.max_length;
+        if message.chars().count() > max_length {
+            return Err(Refusal::TooLong { max_length });
+        }
+        let last_sent = &mut self.last_sent[player as usize - 1];
+        let slow_mode = Duration::from_secs(self.config.slow_mode_secs);
+        if let Some(last_sent) = *last_sent {
+            let ready = last_sent + slow_mode;
+            if now < ready {
+                let wait = (ready - now).as_secs_f64().ceil() as u64;
+                return Err(Refusal::SlowMode { wait });
+            }
+        }
+        let message = match self.config.profanity {
+            ProfanityPolicy::Censor => message.censor(),
+            ProfanityPolicy::Reject if message.is_inappropriate() => {
+                return Err(Refusal::Profanity)
+            }
+            ProfanityPolicy::Reject | ProfanityPolicy::Allow => message.to_owned(),
+        };
+        *last_sent = Some(now);
+        Ok(message)
+    }
+
+    fn write_log(&mut self, line: fmt::Arguments) {
+        if let Some(log) = &mut self.log {
+            if let Err(e) = writeln!(log, "{}", line) {
+                warn!("couldn't write to the chat log: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_moderated_and_logged() {
+        let config = ChatConfig {
+            max_length: 10,
+            slow_mode_secs: 5,
+            ..ChatConfig::default()
+        };
+        let dir = std::env::temp_dir().join(format!("8bit-duels-chat-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut chat = Chat::new(config).with_log(&dir, 7).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(
+            chat.moderate(1, "alice", false, "gl hf", now),
+            Ok("gl hf".to_owned())
+        );
+        // each player has their own slow mode timer
+        assert_eq!(
+            chat.moderate(2, "bob", false, "you too", now),
+            Ok("you too".to_owned())
+        );
+        assert_eq!(
+            chat.moderate(
+                1,
+                "alice",
+                false,
+                "hello?",
+                now + Duration::from_millis(1500)
+            ),
+            Err(Refusal::SlowMode { wait: 4 })
+        );
+        assert_eq!(
+            chat.moderate(
+                1,
+                "alice",
+                false,
+                "héllo héllo",
+                now + Duration::from_secs(6)
+            ),
+            Err(Refusal::TooLong { max_length: 10 })
+        );
+        // refused messages don't restart the timer
+        assert_eq!(
+            chat.moderate(1, "alice", false, "héllo", now + Duration::from_secs(6)),
+            Ok("héllo".to_owned())
+        );
+        assert_eq!(
+            chat.moderate(2, "bob", true, "hi", now + Duration::from_secs(6)),
+            Err(Refusal::Muted)
+        );
+        assert_eq!(
+            chat.moderate(2, "bob", false, "  ", now),
+            Err(Refusal::Empty)
+        );
+        drop(chat);
+
+        let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        assert!(entry.file_name().to_string_lossy().starts_with("7-"));
+        let log = fs::read_to_string(entry.path()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[1], "[00:00] alice: gl hf");
+        assert_eq!(
+            lines[3],
+            "[00:01] alice (refused: Slow mode is on, wait 4s): hello?"
+        );
+        assert_eq!(lines[6], "[00:06] bob (refused: You are muted): hi");
+    }
+}
@@ -0,0 +1,170 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use common::messages::{AbilityEvent, ClientMessage};
+use common::rules::RuleSet;
+use log::warn;
+use serde::Serialize;
+
+// Players are 1 and 2 as in `Game`, the record carries their client ids.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum MatchEvent<'a> {
+    Created {
+        addresses: [String; 2],
+    },
+    PlayerReady {
+        player: u8,
+        username: &'a str,
+    },
+    Started {
+        usernames: [&'a str; 2],
+        rules: &'a RuleSet,
+    },
+    TurnStarted {
+        player: u8,
+    },
+    TurnTimedOut {
+        player: u8,
+    },
+    Spawn {
+        player: u8,
+        card: String,
+        at: [i32; 2],
+    },
+    Move {
+        player: u8,
+        from: [i32; 2],
+        to: [i32; 2],
+    },
+    Attack {
+        player: u8,
+        from: [i32; 2],
+        to: [i32; 2],
+        damage: f32,
+        target_hp: f32,
+        killed: bool,
+        // what the target dealt back, if it did
+        retaliation: Option<f32>,
+        attacker_killed: bool,
+    },
+    // at the start of a turn, by Heal or Regenerate
+    Healed {
+        at: [i32; 2],
+        hp: f32,
+    },
+    // what triggered abilities did, see `ServerMessage::AbilityEvents`
+    Abilities {
+        events: &'a [AbilityEvent],
+    },
+    // an action the server refused, as the player sent it
+    Rejected {
+        player: u8,
+        action: &'a ClientMessage,
+    },
+    Chat {
+        player: u8,
+        message: &'a str,
+    },
+    PlayerLeft {
+        player: u8,
+    },
+    Ended {
+        winner: Option<u8>,
+        reason: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp_ms: u128,
+    game: u64,
+    players: [u64; 2],
+    // 0 until the first turn starts
+    turn: u32,
+    #[serde(flatten)]
+    event: &'a MatchEvent<'a>,
+}
+
+// A JSON-lines file with one record for everything that happens in a match.
+// Every record has the game id, so one match can be grepped out of a busy
+// server's log with `grep '"game":12,'`.
+#[derive(Default)]
+pub struct MatchLog {
+    // `None` when disabled
+    out: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl MatchLog {
+    // `-` for stdout
+    pub fn open(path: &Path) -> io::Result<MatchLog> {
+        let out: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Box::new(LineWriter::new(file))
+        };
+        Ok(MatchLog {
+            out: Some(Mutex::new(out)),
+        })
+    }
+
+    pub fn record(&self, game: u64, players: [u64; 2], turn: u32, event: &MatchEvent) {
+        let out = match &self.out {
+            Some(out) => out,
+            None => return,
+        };
+        let line = to_line(game, players, turn, event);
+        if let Err(e) = writeln!(out.lock().unwrap(), "{}", line) {
+            warn!("couldn't write to the match log: {}", e);
+        }
+    }
+}
+
+fn to_line(game: u64, players: [u64; 2], turn: u32, event: &MatchEvent) -> String {
+    let record = Record {
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis(),
+        game,
+        players,
+        turn,
+        event,
+    };
+    serde_json::to_string(&record).expect("match events always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn records_are_flat_json() {
+        let line = to_line(
+            12,
+            [3, 4],
+            5,
+            &MatchEvent::Move {
+                player: 2,
+                from: [1, 1],
+                to: [1, 2],
+            },
+        );
+        assert!(!line.contains('\n'));
+        assert!(line.contains("\"game\":12,"));
+        let record: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["event"], "move");
+        assert_eq!(record["players"], serde_json::json!([3, 4]));
+        assert_eq!(record["turn"], 5);
+        assert_eq!(record["player"], 2);
+        assert_eq!(record["to"], serde_json::json!([1, 2]));
+    }
+}
@@ -1,307 +1,871 @@
-use std::thread;
+mod chat;
+mod match_log;
+mod triggers;
 
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::metrics::METRICS;
 use crate::net::client::Client;
-use crate::to_p2_x;
-use crate::to_p2_y;
-use crate::utils::{self, WritePacket};
-use rustrict::CensorStr;
+use crate::state::{GameCommand, ServerState};
+use crate::utils::WritePacket;
+use tokio::sync::mpsc;
+use tokio::time::{self, Instant};
 
-use common::card::{Card, CardAbility, CardCollection, CardEntity};
-use common::messages::{ClientMessage, ServerMessage};
+use common::board;
+use common::card::{Card, CardAbility, CardCollection, CardEntity, Trigger};
+use common::map::Terrain;
+use common::messages::{AttackResult, ClientMessage, ServerMessage};
+use common::rules::RuleSet;
+use common::status::Status;
 
+use chat::Chat;
 use log::{info, warn};
+pub use match_log::MatchLog;
+use match_log::MatchEvent;
+use triggers::{Fired, Resolution};
 
 pub struct Game {
+    id: u64,
     client_1: Client,
     client_2: Client,
-    game_board: [[Option<CardEntity>; 5]; 9],
+    game_board: triggers::Board,
+    p1_username: String,
+    p2_username: String,
+    player_1_pawns: i32,
+    player_2_pawns: i32,
+    player_1_spirits: i32,
+    player_2_spirits: i32,
+    // names of the cards each player brought, only those can be played
+    player_1_deck: Vec<String>,
+    player_2_deck: Vec<String>,
+    cards: CardCollection,
+    is_player_1_turn: bool,
+    // counts from 1, both players' turns included
+    turn: u32,
+    rules: RuleSet,
+    turn_deadline: Option<Instant>,
+    state: Arc<ServerState>,
+    control: mpsc::UnboundedReceiver<GameCommand>,
+    chat: Chat,
 }
 
 impl Game {
-    pub fn new(client_1: Client, client_2: Client) -> Game {
-        Game {
+    // registers the game with `state`, where the admin console can see it
+    pub fn new(
+        client_1: Client,
+        client_2: Client,
+        config: Arc<Config>,
+        state: Arc<ServerState>,
+    ) -> Game {
+        let (id, control) = state.add_game([client_1.id(), client_2.id()]);
+        let mut chat = Chat::new(config.chat.clone());
+        if let Some(dir) = config.chat_log_dir() {
+            chat = match chat.with_log(&dir, id) {
+                Ok(chat) => chat,
+                Err(e) => {
+                    warn!("couldn't open a chat log for game {}: {}", id, e);
+                    Chat::new(config.chat.clone())
+                }
+            };
+        }
+        let rules = config.rule_set();
+        let game = Game {
+            id,
             client_1,
             client_2,
-            game_board: [
-                [None, None, None, None, None],
-                [None, None, None, None, None],
-                [None, None, None, None, None],
-                [None, None, None, None, None],
-                [None, None, None, None, None],
-                [None, None, None, None, None],
-                [None, None, None, None, None],
-                [None, None, None, None, None],
-                [None, None, None, None, None],
-            ],
+            game_board: triggers::empty_board(&rules.map),
+            p1_username: "".to_owned(),
+            p2_username: "".to_owned(),
+            player_1_pawns: rules.starting_pawns,
+            player_2_pawns: rules.starting_pawns,
+            player_1_spirits: rules.starting_spirits,
+            player_2_spirits: rules.starting_spirits,
+            player_1_deck: Vec::new(),
+            player_2_deck: Vec::new(),
+            cards: CardCollection::new(),
+            is_player_1_turn: true,
+            turn: 0,
+            rules,
+            turn_deadline: None,
+            state,
+            control,
+            chat,
+        };
+        game.log(MatchEvent::Created {
+            addresses: [game.client_1.addr(), game.client_2.addr()].map(|addr| addr.to_string()),
+        });
+        game
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn log(&self, event: MatchEvent) {
+        self.state.match_log().record(
+            self.id,
+            [self.client_1.id(), self.client_2.id()],
+            self.turn,
+            &event,
+        );
+    }
+
+    fn player(is_player_1: bool) -> u8 {
+        if is_player_1 {
+            1
+        } else {
+            2
         }
     }
 
-    pub fn run(&mut self) {
-        let queue_1 = self.client_1.get_packet_queue();
-        let queue_2 = self.client_2.get_packet_queue();
-        let out_1 = self.client_1.get_stream();
-        let out_2 = self.client_2.get_stream();
-        let cards = CardCollection::new();
-        let game_board = self.game_board.clone();
-        let mut p1_username = "".to_owned();
-        let mut p2_username = "".to_owned();
-        thread::spawn(
-            closure::closure!(move queue_1, move queue_2, move cards, move mut game_board, ||{
-                let mut deck_1: Option<Vec<Card>> = None;
-                let mut deck_2: Option<Vec<Card>> = None;
-                let mut player_1: bool = true;
-                let mut player_1_pawns: i32 = 6;
-                let mut player_2_pawns: i32 = 6;
-                let mut player_1_spirits: i32 = 8;
-                let mut player_2_spirits: i32 = 8;
-                // first check to get player decks
-                loop{
-                    let mut guard;
-                    if deck_1.is_some(){
-                        guard = queue_2.lock().unwrap();
-                        player_1 = false;
-                    }else{
-                        guard = queue_1.lock().unwrap();
-                        player_1 = true;
-                    }
-                    if let Some(message) = guard.pop_front(){
-                        match message {
-                            ClientMessage::PlayerInfo(username, deck) => {
-                                if player_1{
-                                    p1_username = username;
-                                    deck_1 = Some(deck);
-                                }
-                                else{
-                                    p2_username = username;
-                                    deck_2 = Some(deck);
-                                    break;
-                                }
-                            }
-                            ClientMessage::MoveTroop(_,_,_,_) => {}
-                            ClientMessage::AttackTroop(_,_,_,_) => {}
-                            _ => {}
+    pub async fn run(mut self) {
+        self.play().await;
+        self.state.remove_game(self.id);
+    }
 
-                        }
+    // Runs the whole match. The task sleeps until one of the players sends
+    // something, the turn timer runs out or an admin steps in.
+    async fn play(&mut self) {
+        // first get player decks
+        let mut p1_ready = false;
+        let mut p2_ready = false;
+        while !(p1_ready && p2_ready) {
+            let (from_p1, message) = tokio::select! {
+                message = self.client_1.recv() => (true, message),
+                message = self.client_2.recv() => (false, message),
+                Some(command) = self.control.recv() => {
+                    self.handle_command(command);
+                    return;
+                }
+            };
+            match message {
+                Some(ClientMessage::PlayerInfo(username, deck)) => {
+                    let client = if from_p1 { &self.client_1 } else { &self.client_2 };
+                    self.state.set_username(client.id(), &username);
+                    if let Some(ban) = self.state.player_ban(client.addr(), &username) {
+                        info!(
+                            "{} is banned as {}, ending game {}",
+                            username, ban.target, self.id
+                        );
+                        client
+                            .handle()
+                            .send(ServerMessage::Announcement(ban.notice()));
+                        client.kick();
+                        self.abandon(from_p1);
+                        return;
+                    }
+                    self.log(MatchEvent::PlayerReady {
+                        player: Self::player(from_p1),
+                        username: &username,
+                    });
+                    let deck = deck.iter().map(Card::get_name).collect();
+                    if from_p1 {
+                        self.p1_username = username;
+                        self.player_1_deck = deck;
+                        p1_ready = true;
+                    } else {
+                        self.p2_username = username;
+                        self.player_2_deck = deck;
+                        p2_ready = true;
                     }
                 }
-                let mut guard = out_1.lock().unwrap();
-                guard.write_packet(ServerMessage::StartGame(true));
-                guard.write_packet(ServerMessage::StartTurn);
-                guard = out_2.lock().unwrap();
-                guard.write_packet(ServerMessage::StartGame(false));
-                drop(guard);
-                let mut is_player_1_turn = true;
-
-                info!("starting game");
-                'game_loop: loop{
-                    let mut queue_guard;
-                    let mut queue_guard_2;
-                    if is_player_1_turn{
-                        queue_guard = queue_1.lock().unwrap();
-                        queue_guard_2 = queue_2.lock().unwrap();
+                Some(_) => {}
+                None => {
+                    self.abandon(from_p1);
+                    return;
+                }
+            }
+        }
+        self.client_1
+            .write_packet(ServerMessage::StartGame(true, self.rules.clone()));
+        self.client_2
+            .write_packet(ServerMessage::StartGame(false, self.rules.clone()));
+        info!("game {} has begun", self.id);
+        self.log(MatchEvent::Started {
+            usernames: [&self.p1_username, &self.p2_username],
+            rules: &self.rules,
+        });
+        self.start_turn();
+
+        loop {
+            let (from_p1, message) = tokio::select! {
+                message = self.client_1.recv() => (true, message),
+                message = self.client_2.recv() => (false, message),
+                _ = Self::turn_timeout(self.turn_deadline) => {
+                    info!("turn {} of game {} timed out", self.turn, self.id);
+                    self.log(MatchEvent::TurnTimedOut {
+                        player: Self::player(self.is_player_1_turn),
+                    });
+                    if self.is_player_1_turn {
+                        self.client_1.write_packet(ServerMessage::TurnTimedOut);
+                    } else {
+                        self.client_2.write_packet(ServerMessage::TurnTimedOut);
                     }
-                    else{
-                        queue_guard = queue_2.lock().unwrap();
-                        queue_guard_2 = queue_1.lock().unwrap();
+                    self.end_turn();
+                    continue;
+                }
+                Some(command) = self.control.recv() => {
+                    self.handle_command(command);
+                    break;
+                }
+            };
+            let message = match message {
+                Some(message) => message,
+                None => {
+                    self.abandon(from_p1);
+                    break;
+                }
+            };
+            if let ClientMessage::ChatMessage(message) = message {
+                self.chat(from_p1, message);
+                continue;
+            }
+            // everything else has to wait for the sender's turn
+            if from_p1 != self.is_player_1_turn {
+                self.reject(from_p1, &message);
+                continue;
+            }
+            if let Some(p1_won) = self.handle_action(&message) {
+                self.client_1.write_packet(ServerMessage::EndGame(p1_won));
+                self.client_2.write_packet(ServerMessage::EndGame(!p1_won));
+                self.log(MatchEvent::Ended {
+                    winner: Some(Self::player(p1_won)),
+                    reason: "reached_end",
+                });
+                break;
+            }
+        }
+    }
+
+    async fn turn_timeout(deadline: Option<Instant>) {
+        match deadline {
+            Some(deadline) => time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    // every command ends the game for now
+    fn handle_command(&mut self, command: GameCommand) {
+        let (reason, announcement) = match command {
+            GameCommand::ForceEnd => ("admin", "An admin ended this game"),
+            GameCommand::Shutdown => ("shutdown", "The server is shutting down"),
+        };
+        info!("game {} ended: {}", self.id, reason);
+        self.log(MatchEvent::Ended {
+            winner: None,
+            reason,
+        });
+        for client in [&mut self.client_1, &mut self.client_2] {
+            client.write_packet(ServerMessage::Announcement(announcement.to_owned()));
+            client.write_packet(ServerMessage::EndGame(false));
+        }
+    }
+
+    // the remaining player wins when the other one disconnects
+    fn abandon(&mut self, p1_left: bool) {
+        info!("a player left, ending game {}", self.id);
+        self.log(MatchEvent::PlayerLeft {
+            player: Self::player(p1_left),
+        });
+        self.log(MatchEvent::Ended {
+            winner: Some(Self::player(!p1_left)),
+            reason: "abandoned",
+        });
+        if p1_left {
+            self.client_2.write_packet(ServerMessage::EndGame(true));
+        } else {
+            self.client_1.write_packet(ServerMessage::EndGame(true));
+        }
+    }
+
+    fn start_turn(&mut self) {
+        self.turn += 1;
+        self.log(MatchEvent::TurnStarted {
+            player: Self::player(self.is_player_1_turn),
+        });
+        let shrines = self
+            .game_board
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|troop| {
+                troop.is_owned_by_p1() == self.is_player_1_turn
+                    && self.rules.map.terrain(troop.get_x_pos(), troop.get_y_pos())
+                        == Some(Terrain::Shrine)
+            })
+            .count() as i32;
+        let income = self.rules.spirit_income + shrines * self.rules.shrine_income;
+        if self.is_player_1_turn {
+            self.player_1_spirits += income;
+            self.client_1.write_packet(ServerMessage::StartTurn);
+        } else {
+            self.player_2_spirits += income;
+            self.client_2.write_packet(ServerMessage::StartTurn);
+        }
+        self.send_currency();
+        self.heal_at_turn_start();
+        let fired = triggers::fired_by_all(
+            &self.game_board,
+            Trigger::OnTurnStart,
+            self.is_player_1_turn,
+        );
+        self.resolve_triggers(Resolution::default(), fired);
+        self.turn_deadline = self.rules.turn_timer().map(|timer| Instant::now() + timer);
+    }
+
+    // Regenerate and Heal, for the troops of the player whose turn starts
+    fn heal_at_turn_start(&mut self) {
+        let healed = triggers::heal_troops(&mut self.game_board, self.is_player_1_turn);
+        for (x, y) in healed {
+            let hp = match &self.game_board[y as usize][x as usize] {
+                Some(card_entity) => card_entity.current_hp,
+                None => continue,
+            };
+            self.log(MatchEvent::Healed { at: [x, y], hp });
+            self.client_1
+                .write_packet(ServerMessage::HpChanged { x, y, hp });
+            let (x, y) = self.rules.map.flip((x, y));
+            self.client_2
+                .write_packet(ServerMessage::HpChanged { x, y, hp });
+        }
+    }
+
+    fn end_turn(&mut self) {
+        let fired =
+            triggers::fired_by_all(&self.game_board, Trigger::OnTurnEnd, self.is_player_1_turn);
+        self.resolve_triggers(Resolution::default(), fired);
+        self.is_player_1_turn = !self.is_player_1_turn;
+        let (resolution, fired) =
+            triggers::reset_troops(&mut self.game_board, self.is_player_1_turn);
+        self.send_troops(self.is_player_1_turn);
+        self.resolve_triggers(resolution, fired);
+        self.start_turn();
+    }
+
+    // Sends both players one player's troops as they are on the board.
+    fn send_troops(&mut self, owned_by_p1: bool) {
+        let troops: Vec<CardEntity> = self
+            .game_board
+            .iter()
+            .flatten()
+            .flatten()
+            .filter(|troop| troop.is_owned_by_p1() == owned_by_p1)
+            .cloned()
+            .collect();
+        if troops.is_empty() {
+            return;
+        }
+        let for_player_2 = troops
+            .iter()
+            .cloned()
+            .map(|troop| triggers::troop_for_player_2(&self.rules.map, troop))
+            .collect();
+        self.client_1
+            .write_packet(ServerMessage::TroopsChanged(troops));
+        self.client_2
+            .write_packet(ServerMessage::TroopsChanged(for_player_2));
+    }
+
+    // Tells each player how many pawns and spirits they have.
+    fn send_currency(&mut self) {
+        self.client_1.write_packet(ServerMessage::Currency {
+            pawns: self.player_1_pawns,
+            spirits: self.player_1_spirits,
+        });
+        self.client_2.write_packet(ServerMessage::Currency {
+            pawns: self.player_2_pawns,
+            spirits: self.player_2_spirits,
+        });
+    }
+
+    // Resolves triggered abilities after what already happened in
+    // `resolution`, and tells both players about all of it. Troops that died
+    // pay out like any other kill, see `pay_for_kill`.
+    fn resolve_triggers(&mut self, mut resolution: Resolution, fired: Vec<Fired>) {
+        triggers::resolve(
+            &mut self.game_board,
+            &self.rules.map,
+            &mut resolution,
+            fired,
+        );
+        if resolution.events.is_empty() {
+            return;
+        }
+        for dead in &resolution.died {
+            self.pay_for_kill(dead, false);
+        }
+        if !resolution.died.is_empty() {
+            self.send_currency();
+        }
+        self.log(MatchEvent::Abilities {
+            events: &resolution.events,
+        });
+        let for_player_2 = resolution
+            .events
+            .iter()
+            .map(|event| triggers::for_player_2(&self.rules.map, event))
+            .collect();
+        self.client_1
+            .write_packet(ServerMessage::AbilityEvents(resolution.events));
+        self.client_2
+            .write_packet(ServerMessage::AbilityEvents(for_player_2));
+    }
+
+    fn reward_kill(&mut self, killer: &CardEntity, killed: &CardEntity) {
+        let spirit_collector = killer
+            .get_card()
+            .get_abilities()
+            .contains(&CardAbility::SpiritCollector);
+        self.pay_for_kill(killed, spirit_collector);
+    }
+
+    // The owner of `killed` gets the pawn back, the other player the kill
+    // reward. Summoned troops took neither a pawn nor spirits to play, so
+    // they aren't worth anything.
+    fn pay_for_kill(&mut self, killed: &CardEntity, spirit_collector: bool) {
+        if killed.is_summoned() {
+            return;
+        }
+        let spirits = self
+            .rules
+            .kill_reward(killed.get_card().get_cost(), spirit_collector);
+        if killed.is_owned_by_p1() {
+            self.player_1_pawns += 1;
+            self.player_2_spirits += spirits;
+        } else {
+            self.player_2_pawns += 1;
+            self.player_1_spirits += spirits;
+        }
+    }
+
+    // where the taunting troops of one player are
+    fn taunting(game_board: &triggers::Board, owned_by_p1: bool) -> Vec<(i32, i32)> {
+        let mut taunting = Vec::new();
+        for (y, row) in game_board.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                if let Some(card_entity) = tile {
+                    if card_entity.is_owned_by_p1() == owned_by_p1
+                        && card_entity.has_status(Status::Taunt)
+                    {
+                        taunting.push((x as i32, y as i32));
                     }
-                    let mut index_list: Vec<usize> = Vec::new();
-                    for (index, packet) in queue_guard_2.iter().enumerate() {
-                        if let ClientMessage::ChatMessage(message) = packet {
-                            if message.len() > 20{
-                                continue;
+                }
+            }
+        }
+        taunting
+    }
+
+    // Passes a message on to both players, unless the chat rules stop it.
+    fn chat(&mut self, from_p1: bool, message: String) {
+        let (sender, username) = if from_p1 {
+            (&mut self.client_1, &self.p1_username)
+        } else {
+            (&mut self.client_2, &self.p2_username)
+        };
+        let muted = self.state.is_muted(sender.addr(), username);
+        let player = Self::player(from_p1);
+        let text = match self
+            .chat
+            .moderate(player, username, muted, &message, Instant::now())
+        {
+            Ok(text) => text,
+            Err(refusal) => {
+                sender.write_packet(ServerMessage::ChatRejected(refusal.to_string()));
+                self.reject(from_p1, &ClientMessage::ChatMessage(message));
+                return;
+            }
+        };
+        let line = format!("{}: {}", username, text);
+        self.log(MatchEvent::Chat {
+            player,
+            message: &text,
+        });
+        self.client_1
+            .write_packet(ServerMessage::ChatMessage(line.clone()));
+        self.client_2.write_packet(ServerMessage::ChatMessage(line));
+    }
+
+    fn reject(&self, from_p1: bool, action: &ClientMessage) -> Option<bool> {
+        METRICS.action_rejected();
+        self.log(MatchEvent::Rejected {
+            player: Self::player(from_p1),
+            action,
+        });
+        None
+    }
+
+    // Applies a packet from the player whose turn it is. Returns whether
+    // player 1 won if the packet ended the game.
+    fn handle_action(&mut self, message: &ClientMessage) -> Option<bool> {
+        let is_player_1_turn = self.is_player_1_turn;
+        let player = Self::player(is_player_1_turn);
+        let game_board = &mut self.game_board;
+        match *message {
+            ClientMessage::MoveTroop(mut start_x, mut start_y, mut end_x, mut end_y) => {
+                if !is_player_1_turn {
+                    (start_x, start_y) = self.rules.map.flip((start_x, start_y));
+                    (end_x, end_y) = self.rules.map.flip((end_x, end_y));
+                }
+                if !self.rules.map.in_bounds(start_x, start_y)
+                    || !self.rules.map.in_bounds(end_x, end_y)
+                {
+                    return self.reject(is_player_1_turn, message);
+                }
+                let card_to_move = game_board[start_y as usize][start_x as usize].clone();
+                let where_to_move = game_board[end_y as usize][end_x as usize].clone();
+                let mut card_to_move = match card_to_move {
+                    Some(card) => card,
+                    None => return self.reject(is_player_1_turn, message),
+                };
+
+                let reachable = board::reachable_tiles(
+                    &self.rules.map,
+                    &card_to_move,
+                    (start_x, start_y),
+                    |(x, y)| game_board[y as usize][x as usize].is_some(),
+                );
+                if where_to_move.is_none()
+                    && reachable.contains(&(end_x, end_y))
+                    && is_player_1_turn == card_to_move.is_owned_by_p1()
+                    && !card_to_move.has_attacked()
+                    && !card_to_move.has_moved()
+                    && !card_to_move.has_status(Status::Stun)
+                {
+                    game_board[start_y as usize][start_x as usize] = None;
+                    card_to_move.moved();
+                    card_to_move.set_x_pos(end_x);
+                    card_to_move.set_y_pos(end_y);
+                    game_board[end_y as usize][end_x as usize] = Some(card_to_move);
+                    self.log(MatchEvent::Move {
+                        player,
+                        from: [start_x, start_y],
+                        to: [end_x, end_y],
+                    });
+                    self.client_1
+                        .write_packet(ServerMessage::MoveTroop(start_x, start_y, end_x, end_y));
+                    let (start_x, start_y) = self.rules.map.flip((start_x, start_y));
+                    let (end_x, end_y) = self.rules.map.flip((end_x, end_y));
+                    self.client_2
+                        .write_packet(ServerMessage::MoveTroop(start_x, start_y, end_x, end_y));
+                } else {
+                    return self.reject(is_player_1_turn, message);
+                }
+            }
+            ClientMessage::AttackTroop(mut start_x, mut start_y, mut end_x, mut end_y) => {
+                if !is_player_1_turn {
+                    (start_x, start_y) = self.rules.map.flip((start_x, start_y));
+                    (end_x, end_y) = self.rules.map.flip((end_x, end_y));
+                }
+                if !self.rules.map.in_bounds(start_x, start_y)
+                    || !self.rules.map.in_bounds(end_x, end_y)
+                {
+                    return self.reject(is_player_1_turn, message);
+                }
+                let card_to_attack = game_board[start_y as usize][start_x as usize].clone();
+                let where_to_attack = game_board[end_y as usize][end_x as usize].clone();
+                let mut card_to_attack = match card_to_attack {
+                    Some(card) => card,
+                    None => return self.reject(is_player_1_turn, message),
+                };
+                let mut where_to_attack = match where_to_attack {
+                    Some(card) => card,
+                    None => return self.reject(is_player_1_turn, message),
+                };
+
+                let taunting = Self::taunting(game_board, !is_player_1_turn);
+                let in_range = board::may_attack(
+                    &card_to_attack.get_card(),
+                    (start_x, start_y),
+                    (end_x, end_y),
+                    &taunting,
+                    |(x, y)| game_board[y as usize][x as usize].is_some(),
+                );
+                if is_player_1_turn == card_to_attack.is_owned_by_p1()
+                    && !card_to_attack.has_attacked()
+                    && where_to_attack.is_owned_by_p1() != is_player_1_turn
+                    && !card_to_attack.has_status(Status::Stun)
+                    && in_range
+                {
+                    card_to_attack.moved();
+                    let card_binding = card_to_attack.get_card();
+                    let abilities = card_binding.get_abilities();
+                    if !card_to_attack.has_status(Status::Silence) {
+                        for ability in &abilities {
+                            if let CardAbility::Inflict { effect } = ability {
+                                where_to_attack.add_status(effect.clone());
                             }
-                            let message = &message.censor();
-                            let final_message = if is_player_1_turn { p2_username.clone() } else { p1_username.clone() } + ": " + &message;
-                            out_1.lock().unwrap().write_packet(ServerMessage::ChatMessage(final_message.clone()));
-                            out_2.lock().unwrap().write_packet(ServerMessage::ChatMessage(final_message));
-                            index_list.push(index);
                         }
                     }
-                    for index in &index_list {
-                        queue_guard_2.remove(*index);
+                    card_to_attack.attacked();
+
+                    let damage = where_to_attack.take_damage(
+                        card_to_attack.get_card().get_damage()
+                            - self.rules.map.defence(end_x, end_y),
+                    );
+                    let target_hp = where_to_attack.current_hp;
+                    let killed = target_hp <= 0.;
+                    let retaliation = if where_to_attack.retaliates(self.rules.retaliation)
+                        && board::in_attack_range(
+                            &where_to_attack.get_card(),
+                            (end_x, end_y),
+                            (start_x, start_y),
+                            |(x, y)| game_board[y as usize][x as usize].is_some(),
+                        ) {
+                        Some(card_to_attack.take_damage(
+                            where_to_attack.get_card().get_damage()
+                                - self.rules.map.defence(start_x, start_y),
+                        ))
+                    } else {
+                        None
+                    };
+                    let attacker_killed = card_to_attack.current_hp <= 0.;
+                    // only troops next to their target take its place, if they
+                    // can stand there
+                    let attacker_at = if killed
+                        && board::distance((start_x, start_y), (end_x, end_y)) == 1
+                        && self.rules.map.passable(end_x, end_y, card_binding.flies())
+                    {
+                        (end_x, end_y)
+                    } else {
+                        (start_x, start_y)
+                    };
+                    card_to_attack.set_x_pos(attacker_at.0);
+                    card_to_attack.set_y_pos(attacker_at.1);
+                    where_to_attack.set_x_pos(end_x);
+                    where_to_attack.set_y_pos(end_y);
+                    let attacker = (!attacker_killed).then(|| card_to_attack.clone());
+                    let target = (!killed).then(|| where_to_attack.clone());
+
+                    game_board[start_y as usize][start_x as usize] = None;
+                    game_board[end_y as usize][end_x as usize] = target.clone();
+                    if let Some(attacker) = &attacker {
+                        game_board[attacker_at.1 as usize][attacker_at.0 as usize] =
+                            Some(attacker.clone());
                     }
-                    drop(queue_guard_2);
-                        if let Some(message) = queue_guard.pop_front() {
-                            if is_player_1_turn {
-                                player_1_spirits += 1;
-                            }else{
-                                player_2_spirits += 1;
-                            }
-                            drop(queue_guard);
-                            match message {
-                                ClientMessage::MoveTroop(mut start_x, mut start_y, mut end_x, mut end_y) => {
-                                    if !is_player_1_turn{
-                                       start_x = 4 - start_x;
-                                       start_y = 8 - start_y;
-                                       end_x = 4 - end_x;
-                                       end_y = 8 - end_y;
-                                    }
-                                    let card_to_move = game_board[start_y as usize][start_x as usize].clone();
-                                    let where_to_move = game_board[end_y as usize][end_x as usize].clone();
-                                    if card_to_move.is_none(){
-                                        continue;
-                                    }
-                                    let mut card_to_move = card_to_move.unwrap();
-
-                                    if
-                                        where_to_move.is_none()
-                                        && is_player_1_turn == card_to_move.is_owned_by_p1()
-                                        && !card_to_move.has_attacked()
-                                        && !card_to_move.has_moved()
-                                        && card_to_move.stun_count == 0
-                                    {
-                                        game_board[start_y as usize][start_x as usize] = None;
-                                        card_to_move.moved();
-                                        game_board[end_y as usize][end_x as usize] = Some(card_to_move);
-                                        guard = out_1.lock().unwrap();
-                                        let mut guard_2 = out_2.lock().unwrap();
-                                        guard.write_packet(ServerMessage::MoveTroop(start_x, start_y, end_x, end_y));
-                                        guard_2.write_packet(ServerMessage::MoveTroop(4 - start_x, 8 - start_y, 4 - end_x, 8 - end_y));
-                                        drop(guard);
-                                        drop(guard_2);
-                                    }
-                                },
-                                ClientMessage::AttackTroop(mut start_x, mut start_y, mut end_x, mut end_y) => {
-                                    if !is_player_1_turn{
-                                       start_x = 4 - start_x;
-                                       start_y = 8 - start_y;
-                                       end_x = 4 - end_x;
-                                       end_y = 8 - end_y;
-                                    }
-                                    let card_to_attack = game_board[start_y as usize][start_x as usize].clone();
-                                        let where_to_attack = game_board[end_y as usize][end_x as usize].clone();
-
-                                        if card_to_attack.is_none() || where_to_attack.is_none(){
-                                            continue;
-                                        }
-                                        let mut card_to_attack = card_to_attack.unwrap();
-                                        let mut where_to_attack = where_to_attack.unwrap();
-
-                                        if
-                                            is_player_1_turn == card_to_attack.is_owned_by_p1()
-                                            && !card_to_attack.has_attacked()
-                                            && where_to_attack.is_owned_by_p1() != is_player_1_turn
-                                            && card_to_attack.stun_count == 0
-                                        {
-                                            card_to_attack.moved();
-                                            let card_binding = card_to_attack.get_card();
-                                            let abilities = card_binding.get_abilities();
-                                            for ability in &abilities {
-                                                if let CardAbility::Stun { amount } = ability {
-                                                    where_to_attack.stun_count += amount;
-                                                }
-                                            }
-                                            card_to_attack.attacked();
-
-                                            where_to_attack.current_hp -= card_to_attack.get_card().get_damage();
-                                            if where_to_attack.current_hp <= 0. {
-                                                game_board[start_y as usize][start_x as usize] = None;
-                                                game_board[end_y as usize][end_x as usize] = Some(card_to_attack.clone());
-                                                let spirits_to_add = if abilities.contains(&CardAbility::SpiritCollector) {
-                                                    where_to_attack.get_card().get_cost()
-                                                } else {
-                                                    where_to_attack.get_card().get_cost() / 2
-                                                };
-                                                if card_to_attack.is_owned_by_p1() {
-                                                    player_1_spirits += spirits_to_add;
-                                                    player_2_pawns += 1;
-                                                } else{
-                                                    player_2_spirits += spirits_to_add;
-                                                    player_1_pawns += 1;
-                                                }
-                                            }
-                                            else{
-                                                game_board[start_y as usize][start_x as usize] = Some(card_to_attack);
-                                                game_board[end_y as usize][end_x as usize] = Some(where_to_attack);
-                                            }
-                                            guard = out_1.lock().unwrap();
-                                            let mut guard_2 = out_2.lock().unwrap();
-                                            guard.write_packet(ServerMessage::AttackTroop(start_x, start_y, end_x, end_y));
-                                            guard_2.write_packet(ServerMessage::AttackTroop(4 - start_x, 8 - start_y, 4 - end_x, 8 - end_y));
-                                            drop(guard);
-                                            drop(guard_2);
-                                        }
-                                },
-                                ClientMessage::EndTurn => {
-                                    if is_player_1_turn {
-                                        out_2.lock().unwrap().write_packet(ServerMessage::StartTurn);
-                                    } else{
-                                        out_1.lock().unwrap().write_packet(ServerMessage::StartTurn);
-                                    }
-                                    is_player_1_turn = !is_player_1_turn;
-                                    for arr in &mut game_board {
-                                       for option in arr{
-                                           if option.is_some() {
-                                               option.as_mut().unwrap().reset();
-                                           }
-                                       }
-                                    }
-                                },
-                                ClientMessage::SpawnCard(card, x, y) => {
-                                    if x > 4 || x < 0 || y > 8 || y < 0 {return;}
-                                    if is_player_1_turn{
-                                        if player_1_pawns < 1 || player_1_spirits < card.get_cost() {
-                                            continue;
-                                        }
-                                    }
-                                    else{
-                                        if player_2_pawns < 1 || player_2_spirits < card.get_cost() {
-                                            continue;
-                                        }
-                                    }
-
-                                    if let Some(_) = game_board[y as usize][x as usize] {
-                                        continue;
-                                    }
-
-                                    let mut card_entity = CardEntity::new(&card, x, y, is_player_1_turn);
-                                    game_board[y as usize][x as usize] = Some(card_entity.clone());
-                                    if !is_player_1_turn {
-                                        player_2_pawns -= 1;
-                                        player_2_spirits -= card.get_cost();
-                                    }
-                                    else{
-                                        player_1_pawns -= 1;
-                                        player_1_spirits -= card.get_cost();
-                                    }
-                                    out_1.lock().unwrap().write_packet(ServerMessage::SpawnCard(card_entity.clone()));
-                                    card_entity.set_x_pos(to_p2_x!(x));
-                                    card_entity.set_y_pos(to_p2_y!(y));
-                                    out_2.lock().unwrap().write_packet(ServerMessage::SpawnCard(card_entity));
-                                },
-                                ClientMessage::WinGame(x, y) => {
-                                    if let Some(card_entity) = &game_board[y as usize][x as usize] {
-                                        if y != 0 && y != 8{
-                                            continue;
-                                        }
-                                        if card_entity.is_owned_by_p1() == is_player_1_turn{
-                                            if is_player_1_turn && !(card_entity.stun_count > 0) && !card_entity.has_moved(){
-                                                out_1.lock().unwrap().write_packet(ServerMessage::EndGame(true));
-                                                out_2.lock().unwrap().write_packet(ServerMessage::EndGame(false));
-                                                break 'game_loop;
-                                            }
-                                            else if !(card_entity.stun_count > 0) && !card_entity.has_moved(){
-                                                out_1.lock().unwrap().write_packet(ServerMessage::EndGame(false));
-                                                out_2.lock().unwrap().write_packet(ServerMessage::EndGame(true));
-                                                break 'game_loop;
-                                            }
-                                        }
-                                    }
-                                }
-                                ClientMessage::ChatMessage(message) => {
-                                    if message.len() > 20{
-                                        continue;
-                                    }
-                                    let message = &message.censor();
-                                    let final_message = if is_player_1_turn { p1_username.clone() } else { p2_username.clone() } + ": " + &message;
-                                    out_1.lock().unwrap().write_packet(ServerMessage::ChatMessage(final_message.clone()));
-                                    out_2.lock().unwrap().write_packet(ServerMessage::ChatMessage(final_message));
-                                }
-                                _ => {}
-                            }
-                        }
+                    if killed {
+                        self.reward_kill(&card_to_attack, &where_to_attack);
+                    }
+                    if attacker_killed {
+                        self.reward_kill(&where_to_attack, &card_to_attack);
+                    }
+                    self.log(MatchEvent::Attack {
+                        player,
+                        from: [start_x, start_y],
+                        to: [end_x, end_y],
+                        damage,
+                        target_hp,
+                        killed,
+                        retaliation,
+                        attacker_killed,
+                    });
+                    let result = AttackResult {
+                        from: (start_x, start_y),
+                        to: (end_x, end_y),
+                        damage,
+                        retaliation,
+                        attacker,
+                        target,
+                    };
+                    self.client_1
+                        .write_packet(ServerMessage::AttackResult(result.clone()));
+                    let result = AttackResult {
+                        from: self.rules.map.flip(result.from),
+                        to: self.rules.map.flip(result.to),
+                        attacker: result
+                            .attacker
+                            .map(|troop| triggers::troop_for_player_2(&self.rules.map, troop)),
+                        target: result
+                            .target
+                            .map(|troop| triggers::troop_for_player_2(&self.rules.map, troop)),
+                        ..result
+                    };
+                    self.client_2
+                        .write_packet(ServerMessage::AttackResult(result));
+                    if killed || attacker_killed {
+                        self.send_currency();
                     }
-            }),
+                    // the attacker's abilities go first, then the target's
+                    let mut fired = Vec::new();
+                    if let Some(attacker) =
+                        &self.game_board[attacker_at.1 as usize][attacker_at.0 as usize]
+                    {
+                        fired.extend(
+                            Fired::new(Trigger::OnAttack, attacker_at, attacker)
+                                .map(|fired| fired.aimed_at((end_x, end_y))),
+                        );
+                    }
+                    if killed {
+                        fired.extend(Fired::new(
+                            Trigger::OnDeath,
+                            (end_x, end_y),
+                            &where_to_attack,
+                        ));
+                    }
+                    if attacker_killed {
+                        fired.extend(Fired::new(
+                            Trigger::OnDeath,
+                            (start_x, start_y),
+                            &card_to_attack,
+                        ));
+                    }
+                    self.resolve_triggers(Resolution::default(), fired);
+                } else {
+                    return self.reject(is_player_1_turn, message);
+                }
+            }
+            ClientMessage::EndTurn => {
+                self.end_turn();
+            }
+            ClientMessage::SpawnCard(ref sent, x, y) => {
+                // only the name is taken from the client, the rest could be
+                // anything
+                let deck = if is_player_1_turn {
+                    &self.player_1_deck
+                } else {
+                    &self.player_2_deck
+                };
+                let card = match self.cards.0.get(&sent.get_name()) {
+                    Some(card) if deck.contains(&sent.get_name()) => card.clone(),
+                    _ => return self.reject(is_player_1_turn, message),
+                };
+                if !self.rules.may_spawn_on((x, y), is_player_1_turn)
+                    || !self.rules.map.passable(x, y, card.flies())
+                {
+                    return self.reject(is_player_1_turn, message);
+                }
+                if is_player_1_turn {
+                    if self.player_1_pawns < 1 || self.player_1_spirits < card.get_cost() {
+                        return self.reject(is_player_1_turn, message);
+                    }
+                } else if self.player_2_pawns < 1 || self.player_2_spirits < card.get_cost() {
+                    return self.reject(is_player_1_turn, message);
+                }
+
+                if game_board[y as usize][x as usize].is_some() {
+                    return self.reject(is_player_1_turn, message);
+                }
+
+                let card_entity = CardEntity::new(&card, x, y, is_player_1_turn);
+                game_board[y as usize][x as usize] = Some(card_entity.clone());
+                if !is_player_1_turn {
+                    self.player_2_pawns -= 1;
+                    self.player_2_spirits -= card.get_cost();
+                } else {
+                    self.player_1_pawns -= 1;
+                    self.player_1_spirits -= card.get_cost();
+                }
+                self.log(MatchEvent::Spawn {
+                    player,
+                    card: card.get_name(),
+                    at: [x, y],
+                });
+                self.client_1
+                    .write_packet(ServerMessage::SpawnCard(card_entity.clone()));
+                self.client_2
+                    .write_packet(ServerMessage::SpawnCard(triggers::troop_for_player_2(
+                        &self.rules.map,
+                        card_entity,
+                    )));
+                self.send_currency();
+                if let Some(spawned) = &self.game_board[y as usize][x as usize] {
+                    let fired = Fired::new(Trigger::OnSpawn, (x, y), spawned);
+                    self.resolve_triggers(Resolution::default(), fired.into_iter().collect());
+                }
+            }
+            ClientMessage::WinGame(x, y) => {
+                if !self.rules.map.is_goal((x, y), is_player_1_turn) {
+                    return self.reject(is_player_1_turn, message);
+                }
+                if let Some(card_entity) = &game_board[y as usize][x as usize] {
+                    if card_entity.is_owned_by_p1() == is_player_1_turn
+                        && !card_entity.has_status(Status::Stun)
+                        && !card_entity.has_moved()
+                    {
+                        return Some(is_player_1_turn);
+                    }
+                }
+                return self.reject(is_player_1_turn, message);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::card::Effect;
+    use tokio::io::DuplexStream;
+
+    // a game between two clients whose other ends are returned, so nobody
+    // disconnects
+    fn game() -> (Game, [DuplexStream; 2]) {
+        let config = Arc::new(Config::default());
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let (stream_1, peer_1) = tokio::io::duplex(1 << 16);
+        let (stream_2, peer_2) = tokio::io::duplex(1 << 16);
+        let game = Game::new(
+            Client::new(stream_1, addr, &config),
+            Client::new(stream_2, addr, &config),
+            Arc::clone(&config),
+            Arc::new(ServerState::default()),
+        );
+        (game, [peer_1, peer_2])
+    }
+
+    #[tokio::test]
+    async fn summoned_troops_are_worth_nothing() {
+        let (mut game, _peers) = game();
+        let currencies = |game: &Game| {
+            [
+                game.player_1_pawns,
+                game.player_1_spirits,
+                game.player_2_pawns,
+                game.player_2_spirits,
+            ]
+        };
+        let before = currencies(&game);
+
+        // player 2's reaper leaves a skeleton behind on (2, 4)
+        let reaper = Card::with_abilities(
+            "reaper",
+            vec![CardAbility::Triggered {
+                trigger: Trigger::OnDeath,
+                effect: Effect::Summon {
+                    card: "skeleton".to_string(),
+                    count: 1,
+                },
+            }],
+        );
+        let reaper = CardEntity::new(&reaper, 2, 4, false);
+        let fired = Fired::new(Trigger::OnDeath, (2, 4), &reaper).unwrap();
+        game.resolve_triggers(Resolution::default(), vec![fired]);
+        let skeleton = game.game_board[4][2].clone().unwrap();
+        assert!(skeleton.is_summoned());
+
+        let collector = Card::with_abilities("kraken", vec![CardAbility::SpiritCollector]);
+        game.reward_kill(&CardEntity::new(&collector, 2, 3, true), &skeleton);
+        assert_eq!(currencies(&game), before);
+
+        // killed by an ability instead
+        let kraken = Card::with_abilities(
+            "kraken",
+            vec![CardAbility::Triggered {
+                trigger: Trigger::OnAttack,
+                effect: Effect::Splash {
+                    damage: 1.,
+                    range: 1,
+                },
+            }],
         );
+        let kraken = CardEntity::new(&kraken, 2, 3, true);
+        game.game_board[3][2] = Some(kraken.clone());
+        game.game_board[4][2].as_mut().unwrap().current_hp = 1.;
+        let fired = Fired::new(Trigger::OnAttack, (2, 3), &kraken).unwrap();
+        game.resolve_triggers(Resolution::default(), vec![fired]);
+        assert!(game.game_board[4][2].is_none());
+        assert_eq!(currencies(&game), before);
     }
 }
@@ -0,0 +1,491 @@
+use std::collections::VecDeque;
+
+use common::board;
+use common::card::{Card, CardAbility, CardEntity, Effect, Trigger};
+use common::map::Map;
+use common::messages::AbilityEvent;
+use common::status::Status;
+
+// rows of tiles, as big as the map
+pub type Board = Vec<Vec<Option<CardEntity>>>;
+
+pub fn empty_board(map: &Map) -> Board {
+    vec![vec![None; map.width() as usize]; map.height() as usize]
+}
+
+// Abilities can set each other off, e.g. a troop summoning itself on spawn,
+// so resolving stops after this many events.
+const MAX_EVENTS: usize = 100;
+
+// A troop's abilities for one trigger, waiting to be resolved.
+pub struct Fired {
+    trigger: Trigger,
+    at: (i32, i32),
+    // where splash damage lands
+    focus: (i32, i32),
+    // the troop as it was when triggered, it may have died since
+    troop: CardEntity,
+    effects: Vec<Effect>,
+}
+
+impl Fired {
+    // `None` when `troop`, standing on `at`, has no ability for `trigger` or
+    // is silenced
+    pub fn new(trigger: Trigger, at: (i32, i32), troop: &CardEntity) -> Option<Fired> {
+        if troop.has_status(Status::Silence) {
+            return None;
+        }
+        let effects: Vec<Effect> = troop
+            .get_card()
+            .get_abilities()
+            .into_iter()
+            .filter_map(|ability| match ability {
+                CardAbility::Triggered {
+                    trigger: on,
+                    effect,
+                } if on == trigger => Some(effect),
+                _ => None,
+            })
+            .collect();
+        if effects.is_empty() {
+            return None;
+        }
+        Some(Fired {
+            trigger,
+            at,
+            focus: at,
+            troop: troop.clone(),
+            effects,
+        })
+    }
+
+    // for attacks, splash damage lands around the target
+    pub fn aimed_at(mut self, focus: (i32, i32)) -> Fired {
+        self.focus = focus;
+        self
+    }
+}
+
+// Everything `trigger` sets off for the troops of one player, row by row.
+pub fn fired_by_all(board: &Board, trigger: Trigger, owned_by_p1: bool) -> Vec<Fired> {
+    let mut fired = Vec::new();
+    for (y, row) in board.iter().enumerate() {
+        for (x, tile) in row.iter().enumerate() {
+            if let Some(troop) = tile {
+                if troop.is_owned_by_p1() == owned_by_p1 {
+                    fired.extend(Fired::new(trigger, (x as i32, y as i32), troop));
+                }
+            }
+        }
+    }
+    fired
+}
+
+#[derive(Default)]
+pub struct Resolution {
+    // with player 1's coordinates
+    pub events: Vec<AbilityEvent>,
+    // troops killed along the way, for handing out pawns and spirits
+    pub died: Vec<CardEntity>,
+}
+
+impl Resolution {
+    // Takes the dead troop on `at` off the board, returns what its death sets
+    // off.
+    fn remove_dead(&mut self, board: &mut Board, (x, y): (i32, i32)) -> Option<Fired> {
+        let dead = board[y as usize][x as usize].take()?;
+        self.events.push(AbilityEvent::Died { x, y });
+        let fired = Fired::new(Trigger::OnDeath, (x, y), &dead);
+        self.died.push(dead);
+        fired
+    }
+}
+
+// Resets one player's troops as their turn starts, see `CardEntity::reset`.
+// Returns the troops their statuses killed, and what that sets off.
+pub fn reset_troops(board: &mut Board, owned_by_p1: bool) -> (Resolution, Vec<Fired>) {
+    let mut resolution = Resolution::default();
+    let mut fired = Vec::new();
+    for y in 0..board.len() {
+        for x in 0..board[y].len() {
+            let troop = match &mut board[y][x] {
+                Some(troop) if troop.is_owned_by_p1() == owned_by_p1 => troop,
+                _ => continue,
+            };
+            troop.reset();
+            if troop.current_hp <= 0. {
+                fired.extend(resolution.remove_dead(board, (x as i32, y as i32)));
+            }
+        }
+    }
+    (resolution, fired)
+}
+
+// Applies Regenerate and Heal for one player's troops as their turn starts.
+// Returns the tiles of the troops that got healed.
+pub fn heal_troops(board: &mut Board, owned_by_p1: bool) -> Vec<(i32, i32)> {
+    let mut troops = Vec::new();
+    let mut healers = Vec::new();
+    for (y, row) in board.iter().enumerate() {
+        for (x, tile) in row.iter().enumerate() {
+            let troop = match tile {
+                Some(troop) if troop.is_owned_by_p1() == owned_by_p1 => troop,
+                _ => continue,
+            };
+            let at = (x as i32, y as i32);
+            troops.push(at);
+            // silenced troops still get healed, they just don't heal
+            if !troop.has_status(Status::Silence) {
+                healers.push((at, troop.get_card().get_abilities()));
+            }
+        }
+    }
+    let mut healed = Vec::new();
+    for (from, abilities) in &healers {
+        for ability in abilities {
+            // healers don't heal themselves, regenerating troops only do
+            let (amount, range) = match *ability {
+                CardAbility::Regenerate { per_turn } => (per_turn, None),
+                CardAbility::Heal { amount, range } => (amount, Some(range as i32)),
+                _ => continue,
+            };
+            for at in &troops {
+                let in_reach = match range {
+                    Some(range) => at != from && board::distance(*from, *at) <= range,
+                    None => at == from,
+                };
+                if !in_reach {
+                    continue;
+                }
+                if let Some(target) = &mut board[at.1 as usize][at.0 as usize] {
+                    if target.heal(amount) > 0. && !healed.contains(at) {
+                        healed.push(*at);
+                    }
+                }
+            }
+        }
+    }
+    healed
+}
+
+// Resolves `fired` in order on `board`. Whatever the abilities set off in
+// turn, summoned troops spawning or hit ones dying, is resolved after them.
+pub fn resolve(board: &mut Board, map: &Map, resolution: &mut Resolution, fired: Vec<Fired>) {
+    let mut queue: VecDeque<Fired> = fired.into();
+    while let Some(fired) = queue.pop_front() {
+        if resolution.events.len() >= MAX_EVENTS {
+            break;
+        }
+        let owner = fired.troop.is_owned_by_p1();
+        // a troop that died before its turn came up doesn't get to act
+        let still_there = matches!(
+            tile(board, fired.at),
+            Some(troop) if troop.is_owned_by_p1() == owner
+                && troop.get_card().get_name() == fired.troop.get_card().get_name()
+        );
+        if fired.trigger != Trigger::OnDeath && !still_there {
+            continue;
+        }
+        let (x, y) = fired.at;
+        resolution.events.push(AbilityEvent::Triggered {
+            x,
+            y,
+            trigger: fired.trigger,
+        });
+        for effect in &fired.effects {
+            match *effect {
+                Effect::Summon { ref card, count } => {
+                    let card = Card::from(card.as_str());
+                    let mut tiles = vec![fired.at];
+                    tiles.extend(board::tiles_around(map, fired.at, 1));
+                    tiles.retain(|&(x, y)| {
+                        tile(board, (x, y)).is_none() && map.passable(x, y, card.flies())
+                    });
+                    for (x, y) in tiles.into_iter().take(count as usize) {
+                        let mut troop = CardEntity::new(&card, x, y, owner);
+                        troop.set_summoned(true);
+                        board[y as usize][x as usize] = Some(troop.clone());
+                        queue.extend(Fired::new(Trigger::OnSpawn, (x, y), &troop));
+                        resolution.events.push(AbilityEvent::Summoned(troop));
+                    }
+                }
+                Effect::Splash { damage, range } => {
+                    for (x, y) in board::tiles_around(map, fired.focus, range as i32) {
+                        let target = match &mut board[y as usize][x as usize] {
+                            Some(target) if target.is_owned_by_p1() != owner => target,
+                            _ => continue,
+                        };
+                        let damage = target.take_damage(damage - map.defence(x, y));
+                        resolution.events.push(AbilityEvent::Damaged {
+                            damage,
+                            troop: target.clone(),
+                        });
+                        if target.current_hp <= 0. {
+                            queue.extend(resolution.remove_dead(board, (x, y)));
+                        }
+                    }
+                }
+                Effect::Buff { attack, hp, range } => {
+                    for (x, y) in board::tiles_around(map, fired.at, range as i32) {
+                        if let Some(ally) = &mut board[y as usize][x as usize] {
+                            if ally.is_owned_by_p1() == owner {
+                                ally.buff(attack, hp);
+                                resolution.events.push(AbilityEvent::Buffed {
+                                    attack,
+                                    hp,
+                                    troop: ally.clone(),
+                                });
+                            }
+                        }
+                    }
+                }
+                Effect::Gain { ref effect } => {
+                    if let Some(troop) = &mut board[y as usize][x as usize] {
+                        troop.add_status(effect.clone());
+                        resolution.events.push(AbilityEvent::StatusAdded {
+                            effect: effect.clone(),
+                            troop: troop.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn tile(board: &Board, (x, y): (i32, i32)) -> &Option<CardEntity> {
+    &board[y as usize][x as usize]
+}
+
+// the same event, as player 2 sees the board
+pub fn for_player_2(map: &Map, event: &AbilityEvent) -> AbilityEvent {
+    match event.clone() {
+        AbilityEvent::Triggered { x, y, trigger } => {
+            let (x, y) = map.flip((x, y));
+            AbilityEvent::Triggered { x, y, trigger }
+        }
+        AbilityEvent::Damaged { damage, troop } => AbilityEvent::Damaged {
+            damage,
+            troop: troop_for_player_2(map, troop),
+        },
+        AbilityEvent::Died { x, y } => {
+            let (x, y) = map.flip((x, y));
+            AbilityEvent::Died { x, y }
+        }
+        AbilityEvent::Summoned(troop) => AbilityEvent::Summoned(troop_for_player_2(map, troop)),
+        AbilityEvent::Buffed { attack, hp, troop } => AbilityEvent::Buffed {
+            attack,
+            hp,
+            troop: troop_for_player_2(map, troop),
+        },
+        AbilityEvent::StatusAdded { effect, troop } => AbilityEvent::StatusAdded {
+            effect,
+            troop: troop_for_player_2(map, troop),
+        },
+    }
+}
+
+pub fn troop_for_player_2(map: &Map, mut troop: CardEntity) -> CardEntity {
+    let (x, y) = map.flip((troop.get_x_pos(), troop.get_y_pos()));
+    troop.set_x_pos(x);
+    troop.set_y_pos(y);
+    troop
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::status::StatusEffect;
+
+    fn splashing_kraken() -> Card {
+        Card::with_abilities(
+            "kraken",
+            vec![CardAbility::Triggered {
+                trigger: Trigger::OnAttack,
+                effect: Effect::Splash {
+                    damage: 1.,
+                    range: 1,
+                },
+            }],
+        )
+    }
+
+    fn reaper_leaving_a_skeleton() -> Card {
+        Card::with_abilities(
+            "reaper",
+            vec![CardAbility::Triggered {
+                trigger: Trigger::OnDeath,
+                effect: Effect::Summon {
+                    card: "skeleton".to_string(),
+                    count: 1,
+                },
+            }],
+        )
+    }
+
+    fn place(board: &mut Board, card: &Card, (x, y): (i32, i32), owned_by_p1: bool) {
+        board[y as usize][x as usize] = Some(CardEntity::new(card, x, y, owned_by_p1));
+    }
+
+    #[test]
+    fn abilities_resolve_in_order() {
+        let map = Map::default();
+        let mut board = empty_board(&map);
+        let reaper = reaper_leaving_a_skeleton();
+        let shielded = Card::with_abilities("skeleton", vec![CardAbility::Shield { charges: 1 }]);
+        place(&mut board, &splashing_kraken(), (2, 4), true);
+        place(&mut board, &reaper, (2, 5), false);
+        place(&mut board, &shielded, (3, 5), false);
+        board[5][2].as_mut().unwrap().current_hp = 1.;
+
+        let kraken = board[4][2].clone().unwrap();
+        let fired = Fired::new(Trigger::OnAttack, (2, 4), &kraken).unwrap();
+        let mut resolution = Resolution::default();
+        let fired = vec![fired.aimed_at((2, 4))];
+        resolve(&mut board, &map, &mut resolution, fired);
+        // the skeleton's shield takes the hit, the reaper dies and leaves a
+        // skeleton where it stood
+        let mut reaper = CardEntity::new(&reaper, 2, 5, false);
+        reaper.current_hp = 0.;
+        let mut shielded = CardEntity::new(&shielded, 3, 5, false);
+        shielded.take_damage(1.);
+        let mut summoned = CardEntity::new(&Card::from("skeleton"), 2, 5, false);
+        summoned.set_summoned(true);
+        assert_eq!(
+            resolution.events,
+            [
+                AbilityEvent::Triggered {
+                    x: 2,
+                    y: 4,
+                    trigger: Trigger::OnAttack
+                },
+                AbilityEvent::Damaged {
+                    damage: 1.,
+                    troop: reaper
+                },
+                AbilityEvent::Died { x: 2, y: 5 },
+                AbilityEvent::Damaged {
+                    damage: 0.,
+                    troop: shielded
+                },
+                AbilityEvent::Triggered {
+                    x: 2,
+                    y: 5,
+                    trigger: Trigger::OnDeath
+                },
+                AbilityEvent::Summoned(summoned),
+            ]
+        );
+        assert_eq!(resolution.died.len(), 1);
+        assert_eq!(board[5][3].as_ref().unwrap().current_hp, 5.);
+        assert_eq!(
+            board[5][2].as_ref().unwrap().get_card().get_name(),
+            "skeleton"
+        );
+
+        // a crow buffs the troops around it when it's played, enemies aside
+        let crow = Card::with_abilities(
+            "crow",
+            vec![CardAbility::Triggered {
+                trigger: Trigger::OnSpawn,
+                effect: Effect::Buff {
+                    attack: 1.,
+                    hp: 0.,
+                    range: 1,
+                },
+            }],
+        );
+        place(&mut board, &crow, (2, 6), false);
+        let crow = board[6][2].clone().unwrap();
+        let fired = Fired::new(Trigger::OnSpawn, (2, 6), &crow).unwrap();
+        let mut resolution = Resolution::default();
+        resolve(&mut board, &map, &mut resolution, vec![fired]);
+        assert_eq!(resolution.events.len(), 3);
+        assert_eq!(board[5][3].as_ref().unwrap().get_card().get_damage(), 4.);
+        assert_eq!(board[4][2].as_ref().unwrap().get_card().get_damage(), 1.);
+        assert!(Fired::new(Trigger::OnDeath, (2, 6), &crow).is_none());
+    }
+
+    #[test]
+    fn forests_soften_splash_damage() {
+        let map = Map::try_from(["1...", "^~..", "...2"].map(String::from).to_vec()).unwrap();
+        let mut board = empty_board(&map);
+        place(&mut board, &splashing_kraken(), (1, 2), true);
+        place(&mut board, &Card::from("reaper"), (0, 1), false);
+        place(&mut board, &Card::from("reaper"), (2, 1), false);
+
+        let kraken = board[2][1].clone().unwrap();
+        let fired = Fired::new(Trigger::OnAttack, (1, 2), &kraken).unwrap();
+        let mut resolution = Resolution::default();
+        resolve(&mut board, &map, &mut resolution, vec![fired]);
+        let damage: Vec<f32> = resolution
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                AbilityEvent::Damaged { damage, .. } => Some(*damage),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(damage, [0., 1.]);
+    }
+
+    #[test]
+    fn silenced_troops_are_healed_but_do_not_heal() {
+        let map = Map::default();
+        let mut board = empty_board(&map);
+        let healer = Card::with_abilities(
+            "reaper",
+            vec![CardAbility::Heal {
+                amount: 2.,
+                range: 1,
+            }],
+        );
+        place(&mut board, &healer, (2, 4), true);
+        place(&mut board, &healer, (2, 5), true);
+        place(&mut board, &Card::from("skeleton"), (3, 4), true);
+        place(&mut board, &Card::from("skeleton"), (3, 5), false);
+        for (x, y) in [(2, 4), (2, 5), (3, 4), (3, 5)] {
+            board[y][x].as_mut().unwrap().current_hp = 1.;
+        }
+        board[5][2]
+            .as_mut()
+            .unwrap()
+            .add_status(StatusEffect::new(Status::Silence, 1));
+
+        let mut healed = heal_troops(&mut board, true);
+        healed.sort_unstable();
+        assert_eq!(healed, [(2, 5), (3, 4)]);
+        let hp = |x: usize, y: usize| board[y][x].as_ref().unwrap().current_hp;
+        // only the healer that isn't silenced did anything
+        assert_eq!(hp(2, 5), 3.);
+        assert_eq!(hp(3, 4), 3.);
+        assert_eq!(hp(2, 4), 1.);
+        assert_eq!(hp(3, 5), 1.);
+    }
+
+    #[test]
+    fn statuses_can_kill_at_turn_start() {
+        let map = Map::default();
+        let mut board = empty_board(&map);
+        place(&mut board, &reaper_leaving_a_skeleton(), (1, 1), true);
+        place(&mut board, &reaper_leaving_a_skeleton(), (3, 3), true);
+        let poison = StatusEffect::damaging(Status::Poison, 6., 1);
+        board[1][1].as_mut().unwrap().add_status(poison.clone());
+        board[3][3].as_mut().unwrap().add_status(poison);
+        board[3][3]
+            .as_mut()
+            .unwrap()
+            .add_status(StatusEffect::new(Status::Silence, 1));
+
+        let (mut resolution, fired) = reset_troops(&mut board, true);
+        // silenced troops don't get to use their abilities, even dying
+        assert_eq!(fired.len(), 1);
+        resolve(&mut board, &map, &mut resolution, fired);
+        assert_eq!(resolution.died.len(), 2);
+        assert!(board[3][3].is_none());
+        assert_eq!(
+            board[1][1].as_ref().unwrap().get_card().get_name(),
+            "skeleton"
+        );
+    }
+}
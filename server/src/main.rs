@@ -1,50 +1,218 @@
+mod admin;
+mod bans;
+mod config;
 mod game;
+mod metrics;
 mod net;
+mod state;
 mod utils;
 
-use std::{
-    env,
-    fmt::format,
-    io::{BufReader, Write},
-    net::{TcpListener, TcpStream},
-    thread,
-};
+use std::{process, sync::Arc, time::Duration};
 
-use common::card::CardCollection;
-use game::Game;
-use log::{info, warn};
+use bans::BanList;
+use clap::Parser;
+use common::messages::ServerMessage;
+use config::{Cli, Config};
+use game::{Game, MatchLog};
+use log::{error, info, warn};
 use net::client::Client;
 use simple_logger::SimpleLogger;
+use state::ServerState;
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, Semaphore},
+    time,
+};
 
-fn main() {
-    SimpleLogger::new().init().unwrap();
-    const port: i32 = 1000;
-    let args: Vec<String> = env::args().collect();
-    let mut address: &str = "0.0.0.0";
-    if let Some(arg) = args.get(0) {
-        if *arg == "dev".to_owned() {
-            address = "127.0.0.1";
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
         }
+    };
+    if cli.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+    SimpleLogger::new()
+        .with_level(config.log_level)
+        .init()
+        .unwrap();
+    let listener: TcpListener = match TcpListener::bind(config.listen_address()).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("couldn't listen on {}: {}", config.listen_address(), e);
+            process::exit(1);
+        }
+    };
+    let tls = match config.tls.as_ref().map(net::tls::acceptor).transpose() {
+        Ok(tls) => tls,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(1);
+        }
+    };
+    let config = Arc::new(config);
+    // clients show up here once their TLS handshake, if any, is done
+    let (ready_tx, mut ready_rx) = mpsc::unbounded_channel();
+    // each running game holds one permit
+    let game_slots = Arc::new(Semaphore::new(config.max_games));
+    let match_log = match config.match_log_path() {
+        Some(path) => match MatchLog::open(&path) {
+            Ok(match_log) => match_log,
+            Err(e) => {
+                error!("couldn't open the match log {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        None => MatchLog::default(),
+    };
+    let bans_path = config.bans_path();
+    let bans = match BanList::load(&bans_path) {
+        Ok(bans) => bans,
+        Err(e) => {
+            error!("couldn't read the ban list {}: {}", bans_path.display(), e);
+            process::exit(1);
+        }
+    };
+    let state = Arc::new(ServerState::new(match_log, bans));
+    tokio::spawn(admin::console(Arc::clone(&state), config.shutdown_grace()));
+    let (signal_tx, mut signals) = mpsc::unbounded_channel();
+    tokio::spawn(forward_signals(signal_tx));
+    if config.metrics.enabled {
+        tokio::spawn(metrics::serve(
+            config.metrics.listen_address(),
+            Arc::clone(&state),
+        ));
     }
-    let listener: TcpListener =
-        TcpListener::bind(format!("{}:{}", address, port)).expect("Couldn't bind port");
     let mut pending: Option<Client> = None;
-    info!("server started");
+    info!(
+        "server started on {}{}",
+        config.listen_address(),
+        if tls.is_some() { " with TLS" } else { "" }
+    );
     loop {
-        match listener.accept() {
-            Ok((stream, _)) => {
-                info!("client connected");
-                if pending.is_none() {
-                    pending = Some(Client::new(stream));
-                } else {
-                    info!("starting game instance");
-                    Game::new(pending.unwrap(), Client::new(stream)).run();
-                    pending = None;
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, addr)) => {
+                    if state.is_draining() {
+                        info!("refusing {}, the server is shutting down", addr);
+                        continue;
+                    }
+                    if let Some(ban) = state.address_ban(addr) {
+                        info!("refusing {}, banned as {}", addr, ban.target);
+                        continue;
+                    }
+                    info!("client connected from {}", addr);
+                    let tls = tls.clone();
+                    let config = Arc::clone(&config);
+                    let ready_tx = ready_tx.clone();
+                    tokio::spawn(async move {
+                        if let Some(client) = Client::accept(stream, addr, tls, &config).await {
+                            let _ = ready_tx.send(client);
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                }
+            },
+            Some(client) = ready_rx.recv() => {
+                if state.is_draining() {
+                    client.kick();
+                    continue;
+                }
+                state.add_connection(client.handle());
+                match pending.take() {
+                    Some(waiting) if waiting.is_connected() => {
+                        let slot = match Arc::clone(&game_slots).try_acquire_owned() {
+                            Ok(slot) => slot,
+                            Err(_) => {
+                                warn!(
+                                    "refusing {}, {} games are already running",
+                                    client.addr(),
+                                    config.max_games
+                                );
+                                client.handle().send(ServerMessage::Disconnect(
+                                    "the server is full, try again later".to_owned(),
+                                ));
+                                client.kick();
+                                pending = Some(waiting);
+                                continue;
+                            }
+                        };
+                        let (p1_addr, p2_addr) = (waiting.addr(), client.addr());
+                        let game =
+                            Game::new(waiting, client, Arc::clone(&config), Arc::clone(&state));
+                        info!("starting game {} for {} and {}", game.id(), p1_addr, p2_addr);
+                        tokio::spawn(async move {
+                            game.run().await;
+                            drop(slot);
+                        });
+                    }
+                    _ => pending = Some(client),
                 }
             }
-            Err(e) => {
-                println!("{}", e);
+            Some(signal) = signals.recv() => {
+                if state.is_draining() {
+                    warn!("received {} again, exiting without waiting for games", signal);
+                    return;
+                }
+                info!(
+                    "received {}, shutting down once the games are over or in {}s",
+                    signal,
+                    config.shutdown_grace_secs
+                );
+                state.start_draining(config.shutdown_grace());
             }
+            _ = state.drained() => {
+                info!("all games are over, shutting down");
+                // returning aborts the writers, give them a moment to flush
+                // the last packets
+                let _ = time::timeout(Duration::from_secs(1), async {
+                    while !state.connections().is_empty() {
+                        time::sleep(Duration::from_millis(20)).await;
+                    }
+                })
+                .await;
+                return;
+            }
+        }
+    }
+}
+
+// Sends the name of every termination signal the process gets.
+#[cfg(unix)]
+async fn forward_signals(signals: mpsc::UnboundedSender<&'static str>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            warn!("couldn't listen for SIGTERM: {}", e);
+            return;
+        }
+    };
+    loop {
+        let name = tokio::select! {
+            Ok(()) = tokio::signal::ctrl_c() => "SIGINT",
+            Some(()) = terminate.recv() => "SIGTERM",
+            else => return,
+        };
+        if signals.send(name).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn forward_signals(signals: mpsc::UnboundedSender<&'static str>) {
+    while tokio::signal::ctrl_c().await.is_ok() {
+        if signals.send("Ctrl+C").is_err() {
+            return;
         }
     }
 }
@@ -52,15 +220,14 @@ fn main() {
 #[cfg(test)]
 mod tests {
 
-    use super::*;
+    use common::card::{Card, CardCollection};
 
     #[test]
     fn card_deserialization_test() {
-        let card_collection = CardCollection::init();
-        // run `cargo test -- --nocapture` to see output
-        println!(
-            "{}",
-            serde_json::to_string(&card_collection.0.get(&String::from("skeleton"))).unwrap()
-        );
+        let card_collection = CardCollection::new();
+        let skeleton = card_collection.0.get("skeleton").unwrap();
+
+        let json = serde_json::to_string(skeleton).unwrap();
+        assert_eq!(serde_json::from_str::<Card>(&json).unwrap(), *skeleton);
     }
 }
@@ -0,0 +1,272 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+
+use crate::state::ServerState;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+// Counters the rest of the server bumps as things happen. Gauges that can be
+// read off `ServerState` (clients, games) are filled in when scraped.
+pub struct Metrics {
+    messages_received: AtomicU64,
+    // stored as f64 bits, updated once a second
+    messages_per_second: AtomicU64,
+    queued_packets: AtomicU64,
+    rejected_actions: AtomicU64,
+    rate_limited_packets: AtomicU64,
+    games_finished: AtomicU64,
+    game_seconds: AtomicU64,
+}
+
+pub static METRICS: Metrics = Metrics {
+    messages_received: AtomicU64::new(0),
+    messages_per_second: AtomicU64::new(0),
+    queued_packets: AtomicU64::new(0),
+    rejected_actions: AtomicU64::new(0),
+    rate_limited_packets: AtomicU64::new(0),
+    games_finished: AtomicU64::new(0),
+    game_seconds: AtomicU64::new(0),
+};
+
+impl Metrics {
+    pub fn message_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn packet_queued(&self) {
+        self.queued_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn packet_dequeued(&self) {
+        self.queued_packets.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // an action the game refused, e.g. a move out of turn or onto a taken tile
+    pub fn action_rejected(&self) {
+        self.rejected_actions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn packet_rate_limited(&self) {
+        self.rate_limited_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn game_finished(&self, length: Duration) {
+        self.games_finished.fetch_add(1, Ordering::Relaxed);
+        self.game_seconds
+            .fetch_add(length.as_secs(), Ordering::Relaxed);
+    }
+
+    // The Prometheus text exposition format.
+    pub fn render(&self, state: &ServerState) -> String {
+        let games_finished = self.games_finished.load(Ordering::Relaxed);
+        let game_seconds = self.game_seconds.load(Ordering::Relaxed);
+        let average_game_length = if games_finished == 0 {
+            0.
+        } else {
+            game_seconds as f64 / games_finished as f64
+        };
+        let metrics: [(&str, &str, &str, String); 9] = [
+            (
+                "connected_clients",
+                "gauge",
+                "Clients currently connected",
+                state.connections().len().to_string(),
+            ),
+            (
+                "active_games",
+                "gauge",
+                "Games currently running",
+                state.games().len().to_string(),
+            ),
+            (
+                "waiting_players",
+                "gauge",
+                "Players waiting for an opponent",
+                state.waiting_players().to_string(),
+            ),
+            (
+                "queued_packets",
+                "gauge",
+                "Packets received but not yet handled by a game",
+                self.queued_packets.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "messages_received_total",
+                "counter",
+                "Packets received from clients",
+                self.messages_received.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "messages_per_second",
+                "gauge",
+                "Packets received from clients over the last second",
+                f64::from_bits(self.messages_per_second.load(Ordering::Relaxed)).to_string(),
+            ),
+            (
+                "rejected_actions_total",
+                "counter",
+                "Game actions refused as invalid",
+                self.rejected_actions.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "rate_limited_packets_total",
+                "counter",
+                "Packets dropped for going over a rate limit",
+                self.rate_limited_packets.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "average_game_length_seconds",
+                "gauge",
+                "Average length of a finished game",
+                average_game_length.to_string(),
+            ),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP eightbit_duels_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE eightbit_duels_{} {}", name, kind);
+            let _ = writeln!(out, "eightbit_duels_{} {}", name, value);
+        }
+        // a summary without quantiles, only its samples have the suffixes
+        let _ = writeln!(
+            out,
+            "# HELP eightbit_duels_game_length_seconds Length of finished games"
+        );
+        let _ = writeln!(out, "# TYPE eightbit_duels_game_length_seconds summary");
+        let _ = writeln!(
+            out,
+            "eightbit_duels_game_length_seconds_sum {}",
+            game_seconds
+        );
+        let _ = writeln!(
+            out,
+            "eightbit_duels_game_length_seconds_count {}",
+            games_finished
+        );
+        out
+    }
+
+    async fn sample_message_rate(&self) {
+        let mut ticker = time::interval(Duration::from_secs(1));
+        let mut last = self.messages_received.load(Ordering::Relaxed);
+        loop {
+            ticker.tick().await;
+            let now = self.messages_received.load(Ordering::Relaxed);
+            let rate = now.saturating_sub(last) as f64;
+            self.messages_per_second
+                .store(rate.to_bits(), Ordering::Relaxed);
+            last = now;
+        }
+    }
+}
+
+// Serves `/metrics` and `/health` over plain HTTP. Meant for a local port
+// only, there's no authentication.
+pub async fn serve(addr: SocketAddr, state: Arc<ServerState>) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("couldn't serve metrics on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("serving metrics on http://{}/metrics", addr);
+    tokio::spawn(METRICS.sample_message_rate());
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let state = Arc::clone(&state);
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, &state).await {
+                        warn!("metrics request from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, state: &ServerState) -> std::io::Result<()> {
+    let request = match time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(request) => request?,
+        Err(_) => return Ok(()),
+    };
+    let (status, body) = route(&request, state);
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// everything up to the blank line that ends the headers
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let len = stream.read(&mut buffer).await?;
+        if len == 0 || request.len() + len > MAX_REQUEST_LEN {
+            break;
+        }
+        request.extend_from_slice(&buffer[..len]);
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+fn route(request: &str, state: &ServerState) -> (&'static str, String) {
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (request_line.next(), request_line.next());
+    if method != Some("GET") {
+        return ("405 Method Not Allowed", "only GET is supported\n".to_owned());
+    }
+    match path {
+        Some("/metrics") => ("200 OK", METRICS.render(state)),
+        // a draining server should get no new players, but isn't broken
+        Some("/health") if state.is_draining() => {
+            ("503 Service Unavailable", "draining\n".to_owned())
+        }
+        Some("/health") => ("200 OK", "ok\n".to_owned()),
+        _ => ("404 Not Found", "try /metrics or /health\n".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes() {
+        let state = ServerState::default();
+        let (status, body) = route("GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n", &state);
+        assert_eq!(status, "200 OK");
+        assert!(body.contains("# TYPE eightbit_duels_active_games gauge\n"));
+        assert!(body.contains("eightbit_duels_connected_clients 0\n"));
+        assert!(body.contains("# TYPE eightbit_duels_game_length_seconds summary\n"));
+        assert!(!body.contains("# TYPE eightbit_duels_game_length_seconds_sum"));
+
+        assert_eq!(route("GET /health HTTP/1.1\r\n\r\n", &state).0, "200 OK");
+        state.start_draining(Duration::from_secs(60));
+        assert_eq!(
+            route("GET /health HTTP/1.1\r\n\r\n", &state).0,
+            "503 Service Unavailable"
+        );
+        assert_eq!(route("GET / HTTP/1.1\r\n\r\n", &state).0, "404 Not Found");
+        assert_eq!(
+            route("POST /metrics HTTP/1.1\r\n\r\n", &state).0,
+            "405 Method Not Allowed"
+        );
+    }
+}
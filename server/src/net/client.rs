@@ -1,35 +1,181 @@
-use std::collections::VecDeque;
-use std::io::BufReader;
-use std::net::TcpStream;
-use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use common::messages::ClientMessage;
+use common::messages::{ClientMessage, ServerMessage};
+use log::warn;
+use tokio::io::{self, AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
 
-use crate::net::threads;
+use crate::config::Config;
+use crate::metrics::METRICS;
+use crate::net::rate_limit::RateLimiter;
+use crate::net::tasks;
+use crate::utils::WritePacket;
+
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
 
 pub struct Client {
-    tcp_stream: Arc<Mutex<TcpStream>>,
-    packet_queue: Arc<Mutex<VecDeque<ClientMessage>>>,
+    id: u64,
+    addr: SocketAddr,
+    packet_queue: mpsc::Receiver<ClientMessage>,
+    outbox: mpsc::UnboundedSender<ServerMessage>,
+    kicked: Arc<Notify>,
+}
+
+// What the rest of the server keeps to reach a client it doesn't own, e.g. to
+// broadcast to it or kick it from the admin console.
+#[derive(Clone)]
+pub struct ClientHandle {
+    id: u64,
+    addr: SocketAddr,
+    outbox: mpsc::UnboundedSender<ServerMessage>,
+    kicked: Arc<Notify>,
 }
 
 impl Client {
-    pub fn new(tcp_stream: TcpStream) -> Client {
-        let queue: Arc<Mutex<VecDeque<ClientMessage>>> = Arc::new(Mutex::new(VecDeque::new()));
-        threads::spawn(
-            Arc::clone(&queue),
-            tcp_stream.try_clone().expect("Couldn't clone TcpStream"),
-        );
+    // `stream` is either a plain `TcpStream` or one wrapped in TLS
+    pub fn new<S>(stream: S, addr: SocketAddr, config: &Config) -> Client
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (queue_tx, queue_rx) = mpsc::channel(config.rate_limits.max_queued_packets);
+        let (outbox_tx, outbox_rx) = mpsc::unbounded_channel();
+        let (done_tx, done_rx) = oneshot::channel();
+        let kicked = Arc::new(Notify::new());
+        let (read_half, write_half) = io::split(stream);
+        tokio::spawn(tasks::read_packets(
+            addr,
+            read_half,
+            queue_tx,
+            outbox_tx.clone(),
+            done_tx,
+            RateLimiter::new(&config.rate_limits, Instant::now()),
+            config.idle_timeout(),
+        ));
+        tokio::spawn(tasks::write_packets(
+            addr,
+            write_half,
+            outbox_rx,
+            done_rx,
+            Arc::clone(&kicked),
+            config.heartbeat_interval(),
+        ));
         Client {
-            tcp_stream: Arc::new(Mutex::new(tcp_stream)),
-            packet_queue: queue,
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            addr,
+            packet_queue: queue_rx,
+            outbox: outbox_tx,
+            kicked,
+        }
+    }
+
+    // Wraps a freshly accepted socket, doing the TLS handshake first when the
+    // server has a certificate. `None` if the handshake failed.
+    pub async fn accept(
+        stream: TcpStream,
+        addr: SocketAddr,
+        tls: Option<TlsAcceptor>,
+        config: &Config,
+    ) -> Option<Client> {
+        let acceptor = match tls {
+            Some(acceptor) => acceptor,
+            None => return Some(Client::new(stream, addr, config)),
+        };
+        match time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+            Ok(Ok(stream)) => Some(Client::new(stream, addr, config)),
+            Ok(Err(e)) => {
+                warn!("TLS handshake with {} failed: {}", addr, e);
+                None
+            }
+            Err(_) => {
+                warn!("TLS handshake with {} timed out", addr);
+                None
+            }
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // false once the socket has been closed from either end
+    pub fn is_connected(&self) -> bool {
+        !self.outbox.is_closed()
+    }
+
+    pub fn handle(&self) -> ClientHandle {
+        ClientHandle {
+            id: self.id,
+            addr: self.addr,
+            outbox: self.outbox.clone(),
+            kicked: Arc::clone(&self.kicked),
         }
     }
 
-    pub fn get_stream(&mut self) -> Arc<Mutex<TcpStream>> {
-        Arc::clone(&self.tcp_stream)
+    // see `ClientHandle::kick`
+    pub fn kick(&self) {
+        self.kicked.notify_one();
+    }
+
+    // `None` means the client is gone. Cancel safe, so it can sit in `select!`.
+    pub async fn recv(&mut self) -> Option<ClientMessage> {
+        let message = self.packet_queue.recv().await;
+        if message.is_some() {
+            METRICS.packet_dequeued();
+        }
+        message
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // whatever the game never got to still counts as queued otherwise
+        self.packet_queue.close();
+        while self.packet_queue.try_recv().is_ok() {
+            METRICS.packet_dequeued();
+        }
+    }
+}
+
+impl WritePacket for Client {
+    fn write_packet(&mut self, packet: ServerMessage) {
+        // a closed outbox means the client left, which `recv` reports
+        let _ = self.outbox.send(packet);
+    }
+}
+
+impl ClientHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn is_connected(&self) -> bool {
+        !self.outbox.is_closed()
+    }
+
+    pub fn send(&self, packet: ServerMessage) {
+        let _ = self.outbox.send(packet);
     }
 
-    pub fn get_packet_queue(&mut self) -> Arc<Mutex<VecDeque<ClientMessage>>> {
-        Arc::clone(&self.packet_queue)
+    // Closes the connection once everything already sent has been written,
+    // so a parting message still arrives. The owner of the `Client` sees it
+    // as a disconnect.
+    pub fn kick(&self) {
+        self.kicked.notify_one();
     }
 }
@@ -1,2 +1,4 @@
 pub mod client;
-pub mod threads;
+pub mod rate_limit;
+pub mod tasks;
+pub mod tls;
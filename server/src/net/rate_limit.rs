@@ -0,0 +1,211 @@
+use std::time::Instant;
+
+use common::messages::ClientMessage;
+
+use crate::config::{Bucket, RateLimitConfig};
+
+// Holds up to `burst` tokens and earns `per_second` back continuously.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bucket: &Bucket, now: Instant) -> TokenBucket {
+        TokenBucket {
+            capacity: bucket.burst as f64,
+            tokens: bucket.burst as f64,
+            per_second: bucket.per_second,
+            updated: now,
+        }
+    }
+
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Chat,
+    // anything that asks the game to do something
+    Action,
+    // handshakes and heartbeats, only limited by the connection bucket
+    Control,
+}
+
+impl Category {
+    pub fn of(message: &ClientMessage) -> Category {
+        match message {
+            ClientMessage::ChatMessage(_) => Category::Chat,
+            ClientMessage::Handshake(_) | ClientMessage::Ping(_) | ClientMessage::Pong(_) => {
+                Category::Control
+            }
+            ClientMessage::PlayerInfo(..)
+            | ClientMessage::MoveTroop(..)
+            | ClientMessage::AttackTroop(..)
+            | ClientMessage::SpawnCard(..)
+            | ClientMessage::EndTurn
+            | ClientMessage::WinGame(..)
+            | ClientMessage::Resign => Category::Action,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Drop,
+    // the peer keeps going over its limits, the reason is for the log
+    Disconnect(String),
+}
+
+// Every packet has to get past the connection bucket and then the bucket for
+// its category. Packets over a limit are dropped and cost a strike; running
+// out of strikes gets the peer disconnected.
+pub struct RateLimiter {
+    connection: TokenBucket,
+    chat: TokenBucket,
+    actions: TokenBucket,
+    strikes: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig, now: Instant) -> RateLimiter {
+        RateLimiter {
+            connection: TokenBucket::new(&config.connection, now),
+            chat: TokenBucket::new(&config.chat, now),
+            actions: TokenBucket::new(&config.actions, now),
+            strikes: TokenBucket::new(&config.strikes, now),
+        }
+    }
+
+    // `None` for packets that couldn't be decoded, which are always a strike
+    pub fn check(&mut self, message: Option<&ClientMessage>, now: Instant) -> Verdict {
+        let reason = if !self.connection.try_take(now) {
+            "too many packets"
+        } else {
+            match message.map(Category::of) {
+                Some(Category::Chat) if !self.chat.try_take(now) => "too many chat messages",
+                Some(Category::Action) if !self.actions.try_take(now) => "too many actions",
+                Some(_) => return Verdict::Allow,
+                None => "malformed packets",
+            }
+        };
+        if self.strikes.try_take(now) {
+            Verdict::Drop
+        } else {
+            Verdict::Disconnect(reason.to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limits() -> RateLimitConfig {
+        RateLimitConfig {
+            connection: Bucket {
+                per_second: 100.,
+                burst: 100,
+            },
+            chat: Bucket {
+                per_second: 1.,
+                burst: 3,
+            },
+            actions: Bucket {
+                per_second: 10.,
+                burst: 10,
+            },
+            strikes: Bucket {
+                per_second: 0.1,
+                burst: 2,
+            },
+            ..RateLimitConfig::default()
+        }
+    }
+
+    #[test]
+    fn bucket_refills_up_to_its_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(
+            &Bucket {
+                per_second: 2.,
+                burst: 2,
+            },
+            start,
+        );
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+        // a long pause doesn't bank more than `burst`
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.try_take(later));
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn chat_flood_is_dropped_then_disconnected() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(&limits(), now);
+        let chat = ClientMessage::ChatMessage("spam".to_owned());
+        for _ in 0..3 {
+            assert_eq!(limiter.check(Some(&chat), now), Verdict::Allow);
+        }
+        // other categories have their own budget
+        assert_eq!(
+            limiter.check(Some(&ClientMessage::EndTurn), now),
+            Verdict::Allow
+        );
+        assert_eq!(limiter.check(Some(&chat), now), Verdict::Drop);
+        assert_eq!(limiter.check(Some(&chat), now), Verdict::Drop);
+        assert!(matches!(
+            limiter.check(Some(&chat), now),
+            Verdict::Disconnect(_)
+        ));
+    }
+
+    #[test]
+    fn heartbeats_only_count_against_the_connection() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(&limits(), now);
+        for _ in 0..100 {
+            assert_eq!(
+                limiter.check(Some(&ClientMessage::Pong(1)), now),
+                Verdict::Allow
+            );
+        }
+        assert_eq!(
+            limiter.check(Some(&ClientMessage::Pong(1)), now),
+            Verdict::Drop
+        );
+    }
+
+    #[test]
+    fn malformed_packets_cost_strikes() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(&limits(), now);
+        assert_eq!(limiter.check(None, now), Verdict::Drop);
+        assert_eq!(limiter.check(None, now), Verdict::Drop);
+        assert_eq!(
+            limiter.check(None, now),
+            Verdict::Disconnect("malformed packets".to_owned())
+        );
+    }
+}
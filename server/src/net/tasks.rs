@@ -0,0 +1,193 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use common::codec::Codec;
+use common::framing::{self, FrameError};
+use common::messages::{ClientMessage, ServerMessage};
+use log::{debug, info, warn};
+
+use crate::metrics::METRICS;
+use crate::net::rate_limit::{RateLimiter, Verdict};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::{oneshot, Notify};
+use tokio::time::{self, MissedTickBehavior};
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, FrameError> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Err(FrameError::Eof),
+        Err(e) => return Err(e.into()),
+    };
+    framing::check_frame_len(len)?;
+    let mut buffer = vec![0; len as usize];
+    reader.read_exact(&mut buffer).await?;
+    Ok(buffer)
+}
+
+// Decodes packets into `queue` until the peer leaves, stays silent for longer
+// than `idle_timeout`, goes over its rate limits once too often, the game
+// drops its end of the queue or the writer stops. `_done` is dropped on the
+// way out, which stops the writer.
+pub async fn read_packets<R: AsyncRead + Unpin>(
+    addr: SocketAddr,
+    mut stream: R,
+    queue: mpsc::Sender<ClientMessage>,
+    outbox: mpsc::UnboundedSender<ServerMessage>,
+    _done: oneshot::Sender<()>,
+    mut limiter: RateLimiter,
+    idle_timeout: Duration,
+) {
+    let mut codec = Codec::Json;
+    let mut first_packet = true;
+    loop {
+        // the writer pings every heartbeat interval, so a live client always
+        // has something to answer
+        let frame = tokio::select! {
+            frame = time::timeout(idle_timeout, read_frame(&mut stream)) => match frame {
+                Ok(frame) => frame,
+                Err(_) => {
+                    warn!("{} stopped responding, dropping it", addr);
+                    break;
+                }
+            },
+            _ = queue.closed() => break,
+            // the writer is gone, most likely because the client was kicked
+            _ = outbox.closed() => break,
+        };
+        let decoded = frame.and_then(|frame| codec.decode::<ClientMessage>(&frame));
+        let verdict = match &decoded {
+            Ok(message) => {
+                METRICS.message_received();
+                limiter.check(Some(message), Instant::now())
+            }
+            Err(FrameError::Malformed(_)) => limiter.check(None, Instant::now()),
+            Err(_) => Verdict::Allow,
+        };
+        match verdict {
+            Verdict::Allow => {}
+            Verdict::Drop => {
+                METRICS.packet_rate_limited();
+                match &decoded {
+                    // the frame was consumed whole, so the stream is still in sync
+                    Err(FrameError::Malformed(e)) => {
+                        warn!("dropping malformed packet from {}: {}", addr, e)
+                    }
+                    _ => debug!("{} is over its rate limit, dropping a packet", addr),
+                }
+                first_packet = false;
+                continue;
+            }
+            Verdict::Disconnect(reason) => {
+                METRICS.packet_rate_limited();
+                warn!("disconnecting {}: {}", addr, reason);
+                break;
+            }
+        }
+        match decoded {
+            Ok(ClientMessage::Handshake(requested)) => {
+                if first_packet {
+                    // the writer switches codecs right after sending this
+                    let _ = outbox.send(ServerMessage::Handshake(requested));
+                    codec = requested;
+                } else {
+                    warn!("{} sent a late handshake", addr);
+                }
+            }
+            Ok(ClientMessage::Ping(value)) => {
+                let _ = outbox.send(ServerMessage::Pong(value));
+            }
+            // only there to show the client is alive
+            Ok(ClientMessage::Pong(_)) => {}
+            Ok(message) => {
+                // counted first, the game may take it off the queue right away
+                METRICS.packet_queued();
+                match queue.try_send(message) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        METRICS.packet_dequeued();
+                        warn!("disconnecting {}: too many packets waiting for the game", addr);
+                        break;
+                    }
+                    Err(TrySendError::Closed(_)) => {
+                        METRICS.packet_dequeued();
+                        break;
+                    }
+                }
+            }
+            // handled with the rate limits above
+            Err(FrameError::Malformed(_)) => {}
+            Err(FrameError::Eof) => {
+                info!("{} disconnected", addr);
+                break;
+            }
+            Err(e) => {
+                warn!("closing connection to {}: {}", addr, e);
+                break;
+            }
+        }
+        first_packet = false;
+    }
+}
+
+pub async fn write_packets<W: AsyncWrite + Unpin>(
+    addr: SocketAddr,
+    mut stream: W,
+    mut outbox: mpsc::UnboundedReceiver<ServerMessage>,
+    mut reader_done: oneshot::Receiver<()>,
+    kicked: Arc<Notify>,
+    heartbeat_interval: Duration,
+) {
+    let mut codec = Codec::Json;
+    let mut heartbeat = time::interval_at(
+        time::Instant::now() + heartbeat_interval,
+        heartbeat_interval,
+    );
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut pings_sent: u64 = 0;
+    loop {
+        // biased so that whatever is still queued (e.g. `EndGame`) goes out
+        // before we notice the reader has stopped or the client was kicked
+        let packet = tokio::select! {
+            biased;
+            packet = outbox.recv() => packet,
+            _ = &mut reader_done => None,
+            _ = kicked.notified() => {
+                info!("kicked {}", addr);
+                None
+            }
+            _ = heartbeat.tick() => {
+                pings_sent += 1;
+                Some(ServerMessage::Ping(pings_sent))
+            }
+        };
+        let packet = match packet {
+            Some(packet) => packet,
+            None => break,
+        };
+        let next_codec = match packet {
+            ServerMessage::Handshake(requested) => requested,
+            _ => codec,
+        };
+        match codec
+            .encode(&packet)
+            .and_then(|payload| framing::frame(&payload))
+        {
+            Ok(bytes) => {
+                // flushing matters for TLS, which buffers inside the session
+                let written = match stream.write_all(&bytes).await {
+                    Ok(()) => stream.flush().await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = written {
+                    warn!("couldn't write to {}: {}", addr, e);
+                    break;
+                }
+            }
+            Err(e) => warn!("couldn't encode packet for {}: {}", addr, e),
+        }
+        codec = next_codec;
+    }
+    let _ = stream.shutdown().await;
+}
@@ -0,0 +1,181 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::{ConfigError, TlsConfig};
+
+fn open(path: &Path) -> Result<BufReader<File>, ConfigError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| ConfigError::Io(path.to_owned(), e))
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>, ConfigError> {
+    let certificates =
+        rustls_pemfile::certs(&mut open(path)?).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+    if certificates.is_empty() {
+        return Err(ConfigError::Invalid(format!(
+            "no certificates in {}",
+            path.display()
+        )));
+    }
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey, ConfigError> {
+    let mut reader = open(path)?;
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|e| ConfigError::Io(path.to_owned(), e))?
+        {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => {}
+            None => {
+                return Err(ConfigError::Invalid(format!(
+                    "no private key in {}",
+                    path.display()
+                )))
+            }
+        }
+    }
+}
+
+pub fn acceptor(config: &TlsConfig) -> Result<TlsAcceptor, ConfigError> {
+    let certificates = load_certificates(&config.certificate)?;
+    let key = load_private_key(&config.private_key)?;
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map_err(|e| ConfigError::Invalid(format!("unusable certificate or key: {}", e)))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::net::client::Client;
+    use common::{
+        codec::Codec,
+        messages::{ClientMessage, ServerMessage},
+    };
+    use std::{fs, path::PathBuf};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::{
+        rustls::{ClientConfig, RootCertStore, ServerName},
+        TlsConnector,
+    };
+
+    // deletes the PEM files once a test is done with them
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // a fresh self-signed certificate for localhost, written out as PEM files
+    fn self_signed(name: &str) -> (TlsConfig, Certificate, TempDir) {
+        let generated = rcgen::generate_simple_self_signed(vec![
+            "localhost".to_owned(),
+            "127.0.0.1".to_owned(),
+        ])
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("8bit-duels-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let certificate: PathBuf = dir.join("cert.pem");
+        let private_key: PathBuf = dir.join("key.pem");
+        fs::write(&certificate, generated.serialize_pem().unwrap()).unwrap();
+        fs::write(&private_key, generated.serialize_private_key_pem()).unwrap();
+        (
+            TlsConfig {
+                certificate,
+                private_key,
+            },
+            Certificate(generated.serialize_der().unwrap()),
+            TempDir(dir),
+        )
+    }
+
+    fn trusting(certificate: Certificate) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        roots.add(&certificate).unwrap();
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        TlsConnector::from(Arc::new(config))
+    }
+
+    #[test]
+    fn bad_files_are_config_errors() {
+        let (mut config, _, _dir) = self_signed("bad-files");
+        config.private_key = config.certificate.clone();
+        assert!(matches!(acceptor(&config), Err(ConfigError::Invalid(_))));
+        config.certificate = config.certificate.with_file_name("missing.pem");
+        assert!(matches!(acceptor(&config), Err(ConfigError::Io(..))));
+    }
+
+    #[tokio::test]
+    async fn handshake_over_tls() {
+        let (tls_config, certificate, _dir) = self_signed("handshake");
+        let acceptor = acceptor(&tls_config).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.unwrap();
+            let mut client = Client::new(stream, peer, &Config::default());
+            client.recv().await
+        });
+
+        // players usually type an IP address, which has to verify as well
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = trusting(certificate)
+            .connect(ServerName::try_from("127.0.0.1").unwrap(), stream)
+            .await
+            .unwrap();
+        for message in [
+            ClientMessage::Handshake(Codec::Json),
+            ClientMessage::EndTurn,
+        ] {
+            let payload = Codec::Json.encode(&message).unwrap();
+            let frame = common::framing::frame(&payload).unwrap();
+            stream.write_all(&frame).await.unwrap();
+        }
+
+        let len = stream.read_u32().await.unwrap();
+        let mut payload = vec![0; len as usize];
+        stream.read_exact(&mut payload).await.unwrap();
+        assert_eq!(
+            Codec::Json.decode::<ServerMessage>(&payload).unwrap(),
+            ServerMessage::Handshake(Codec::Json)
+        );
+        assert_eq!(server.await.unwrap(), Some(ClientMessage::EndTurn));
+    }
+
+    #[tokio::test]
+    async fn untrusted_certificate_is_refused() {
+        let (tls_config, _, _dir) = self_signed("untrusted");
+        let (_, other_certificate, _) = self_signed("untrusted-other");
+        let acceptor = acceptor(&tls_config).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = acceptor.accept(stream).await;
+        });
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let result = trusting(other_certificate)
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await;
+        assert!(result.is_err());
+    }
+}
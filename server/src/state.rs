@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use common::messages::ServerMessage;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::{self, Instant};
+
+use crate::bans::{Ban, BanList, Target};
+use crate::game::MatchLog;
+use crate::metrics::METRICS;
+use crate::net::client::ClientHandle;
+
+// What the admin console can ask of a running game.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GameCommand {
+    // ends the match without a winner
+    ForceEnd,
+    // the same, because the server is going down
+    Shutdown,
+}
+
+#[derive(Clone)]
+pub struct ConnectionInfo {
+    pub handle: ClientHandle,
+    // known once the client has sent its `PlayerInfo`
+    pub username: Option<String>,
+    pub game: Option<u64>,
+}
+
+impl ConnectionInfo {
+    fn matches(&self, target: &Target) -> bool {
+        target.matches(self.handle.addr().ip(), self.username.as_deref())
+    }
+}
+
+#[derive(Clone)]
+pub struct GameInfo {
+    // client ids, player 1 first
+    pub players: [u64; 2],
+    pub started: Instant,
+    control: mpsc::UnboundedSender<GameCommand>,
+}
+
+// Muted players can play, but not chat. Unlike bans these don't outlast
+// the server.
+struct Mute {
+    target: Target,
+    // `None` until unmuted
+    until: Option<Instant>,
+}
+
+// Everything the server knows about its connections and games, shared between
+// the accept loop, the games and the admin console.
+#[derive(Default)]
+pub struct ServerState {
+    connections: Mutex<HashMap<u64, ConnectionInfo>>,
+    games: Mutex<HashMap<u64, GameInfo>>,
+    bans: Mutex<BanList>,
+    mutes: Mutex<Vec<Mute>>,
+    next_game_id: AtomicU64,
+    draining: AtomicBool,
+    shutdown_deadline: Mutex<Option<Instant>>,
+    // woken whenever a game ends or draining starts
+    changed: Notify,
+    match_log: MatchLog,
+}
+
+impl ServerState {
+    pub fn new(match_log: MatchLog, bans: BanList) -> ServerState {
+        ServerState {
+            match_log,
+            bans: Mutex::new(bans),
+            ..ServerState::default()
+        }
+    }
+
+    pub fn match_log(&self) -> &MatchLog {
+        &self.match_log
+    }
+
+    pub fn add_connection(&self, handle: ClientHandle) {
+        let mut connections = self.connections.lock().unwrap();
+        // nothing removes clients that leave, so they're swept out here
+        connections.retain(|_, connection| connection.handle.is_connected());
+        connections.insert(
+            handle.id(),
+            ConnectionInfo {
+                handle,
+                username: None,
+                game: None,
+            },
+        );
+    }
+
+    pub fn set_username(&self, client_id: u64, username: &str) {
+        if let Some(connection) = self.connections.lock().unwrap().get_mut(&client_id) {
+            connection.username = Some(username.to_owned());
+        }
+    }
+
+    // live connections, oldest first
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<ConnectionInfo> = self
+            .connections
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|connection| connection.handle.is_connected())
+            .cloned()
+            .collect();
+        connections.sort_by_key(|connection| connection.handle.id());
+        connections
+    }
+
+    pub fn username(&self, client_id: u64) -> Option<String> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&client_id)
+            .and_then(|connection| connection.username.clone())
+    }
+
+    // Returns the new game's id and where its admin commands arrive.
+    pub fn add_game(&self, players: [u64; 2]) -> (u64, mpsc::UnboundedReceiver<GameCommand>) {
+        let id = self.next_game_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let (control, commands) = mpsc::unbounded_channel();
+        self.games.lock().unwrap().insert(
+            id,
+            GameInfo {
+                players,
+                started: Instant::now(),
+                control,
+            },
+        );
+        let mut connections = self.connections.lock().unwrap();
+        for player in players {
+            if let Some(connection) = connections.get_mut(&player) {
+                connection.game = Some(id);
+            }
+        }
+        (id, commands)
+    }
+
+    pub fn remove_game(&self, id: u64) {
+        let game = self.games.lock().unwrap().remove(&id);
+        if let Some(game) = game {
+            METRICS.game_finished(game.started.elapsed());
+            let mut connections = self.connections.lock().unwrap();
+            for player in game.players {
+                if let Some(connection) = connections.get_mut(&player) {
+                    connection.game = None;
+                }
+            }
+        }
+        self.changed.notify_waiters();
+    }
+
+    // connected, but not in a game yet
+    pub fn waiting_players(&self) -> usize {
+        self.connections()
+            .iter()
+            .filter(|connection| connection.game.is_none())
+            .count()
+    }
+
+    // running games by id
+    pub fn games(&self) -> Vec<(u64, GameInfo)> {
+        let mut games: Vec<(u64, GameInfo)> = self
+            .games
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, game)| (*id, game.clone()))
+            .collect();
+        games.sort_by_key(|(id, _)| *id);
+        games
+    }
+
+    // false if there's no such game
+    pub fn end_game(&self, id: u64) -> bool {
+        match self.games.lock().unwrap().get(&id) {
+            Some(game) => game.control.send(GameCommand::ForceEnd).is_ok(),
+            None => false,
+        }
+    }
+
+    // Tells everyone connected. Returns how many clients that reached.
+    pub fn announce(&self, text: &str) -> usize {
+        let connections = self.connections();
+        for connection in &connections {
+            connection
+                .handle
+                .send(ServerMessage::Announcement(text.to_owned()));
+        }
+        connections.len()
+    }
+
+    // Disconnects every client matching `target`, telling them `reason`
+    // first. Returns how many there were.
+    pub fn kick(&self, target: &Target, reason: &str) -> usize {
+        let kicked: Vec<ConnectionInfo> = self
+            .connections()
+            .into_iter()
+            .filter(|connection| connection.matches(target))
+            .collect();
+        for connection in &kicked {
+            connection
+                .handle
+                .send(ServerMessage::Announcement(reason.to_owned()));
+            connection.handle.kick();
+        }
+        kicked.len()
+    }
+
+    // Bans `ban.target` and kicks whoever it matches. Returns how many were
+    // kicked, and whether the ban could be saved.
+    pub fn ban(&self, ban: Ban) -> (usize, io::Result<()>) {
+        let notice = ban.notice();
+        let target = ban.target.clone();
+        let saved = self.bans.lock().unwrap().add(ban);
+        (self.kick(&target, &notice), saved)
+    }
+
+    // `Ok(false)` if `target` wasn't banned
+    pub fn unban(&self, target: &Target) -> io::Result<bool> {
+        self.bans.lock().unwrap().remove(target)
+    }
+
+    // bans that haven't expired, oldest first
+    pub fn bans(&self) -> Vec<Ban> {
+        self.bans.lock().unwrap().active()
+    }
+
+    pub fn address_ban(&self, addr: SocketAddr) -> Option<Ban> {
+        self.bans.lock().unwrap().find(addr.ip(), None)
+    }
+
+    // a ban on either the address or the username
+    pub fn player_ban(&self, addr: SocketAddr, username: &str) -> Option<Ban> {
+        self.bans.lock().unwrap().find(addr.ip(), Some(username))
+    }
+
+    // Replaces any mute on the same target.
+    pub fn mute(&self, target: Target, length: Option<Duration>) {
+        let mut mutes = self.mutes.lock().unwrap();
+        mutes.retain(|mute| !mute.target.same_as(&target));
+        mutes.push(Mute {
+            target,
+            until: length.map(|length| Instant::now() + length),
+        });
+    }
+
+    // false if `target` wasn't muted
+    pub fn unmute(&self, target: &Target) -> bool {
+        let mut mutes = self.mutes.lock().unwrap();
+        let count = mutes.len();
+        mutes.retain(|mute| !mute.target.same_as(target));
+        mutes.len() != count
+    }
+
+    pub fn is_muted(&self, addr: SocketAddr, username: &str) -> bool {
+        let now = Instant::now();
+        self.mutes.lock().unwrap().iter().any(|mute| {
+            !matches!(mute.until, Some(until) if until <= now)
+                && mute.target.matches(addr.ip(), Some(username))
+        })
+    }
+
+    // Stops new players from joining, tells everyone the server stops in
+    // `grace` and sends away those who aren't in a game. Games still running
+    // once `grace` is up are ended by `drained`. Calling it again can only
+    // bring the deadline forward.
+    pub fn start_draining(&self, grace: Duration) {
+        self.draining.store(true, Ordering::SeqCst);
+        let deadline = {
+            let mut deadline = self.shutdown_deadline.lock().unwrap();
+            let requested = Instant::now() + grace;
+            let earliest = match *deadline {
+                Some(current) if current < requested => current,
+                _ => requested,
+            };
+            *deadline = Some(earliest);
+            earliest
+        };
+        let seconds = deadline
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64()
+            .round() as u64;
+        for connection in self.connections() {
+            connection
+                .handle
+                .send(ServerMessage::ServerShuttingDown { seconds });
+            if connection.game.is_none() {
+                connection.handle.kick();
+            }
+        }
+        self.changed.notify_waiters();
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    // Resolves once draining has started and the last game has ended. Games
+    // that outlast the shutdown deadline are told to stop.
+    pub async fn drained(&self) {
+        loop {
+            // created before checking, so a change in between isn't missed
+            let changed = self.changed.notified();
+            if self.is_draining() && self.games.lock().unwrap().is_empty() {
+                return;
+            }
+            let deadline = *self.shutdown_deadline.lock().unwrap();
+            match deadline {
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = changed => {}
+                        _ = time::sleep_until(deadline) => {
+                            self.shutdown_deadline.lock().unwrap().take();
+                            for (_, game) in self.games() {
+                                let _ = game.control.send(GameCommand::Shutdown);
+                            }
+                        }
+                    }
+                }
+                None => changed.await,
+            }
+        }
+    }
+}